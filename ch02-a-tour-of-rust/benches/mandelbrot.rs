@@ -0,0 +1,121 @@
+//! `escape_time`、`render`、细分渲染和多线程分条带渲染的基准测试。
+//!
+//! 运行`cargo bench -p ch02-a-tour-of-rust`，结果保存在`target/criterion`里，
+//! 之后再次运行会和上一次的结果比较。
+
+use ch02::concurrency::draw::{Band, render, render_band, render_bands, render_subdivide};
+use ch02::concurrency::mandelbrot::escape_time;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use num::Complex;
+use std::hint::black_box;
+use std::thread;
+
+/// 整个曼德勃罗集。
+const FULL_VIEW: (Complex<f64>, Complex<f64>) =
+    (Complex { re: -2.5, im: 1.2 }, Complex { re: 1.0, im: -1.2 });
+
+/// `main.rs`用法示例中的区域，大部分点都在边界附近。
+const SEAHORSE_VIEW: (Complex<f64>, Complex<f64>) = (
+    Complex {
+        re: -1.20,
+        im: 0.35,
+    },
+    Complex { re: -1.0, im: 0.20 },
+);
+
+fn bench_escape_time(c: &mut Criterion) {
+    let mut group = c.benchmark_group("escape_time");
+    let points = [
+        // 集合内部的点要迭代满`limit`次
+        ("inside_origin", Complex { re: 0.0, im: 0.0 }),
+        ("inside_bulb", Complex { re: -1.0, im: 0.0 }),
+        // 集合外部的点很快逃逸
+        ("outside_far", Complex { re: 1.0, im: 1.0 }),
+        ("outside_near_boundary", Complex { re: -0.75, im: 0.1 }),
+    ];
+    for (name, point) in points {
+        group.bench_function(name, |b| {
+            b.iter(|| escape_time(black_box(point), black_box(255)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_render(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render");
+    group.sample_size(10);
+    for (view_name, (upper_left, lower_right)) in [("full", FULL_VIEW), ("seahorse", SEAHORSE_VIEW)]
+    {
+        for bounds in [(100, 75), (400, 300), (1000, 750)] {
+            let mut pixels = vec![0; bounds.0 * bounds.1];
+            group.throughput(Throughput::Elements((bounds.0 * bounds.1) as u64));
+            group.bench_with_input(
+                BenchmarkId::new(view_name, format!("{}x{}", bounds.0, bounds.1)),
+                &bounds,
+                |b, &bounds| b.iter(|| render(&mut pixels, bounds, upper_left, lower_right)),
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_subdivide(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render_subdivide");
+    group.sample_size(10);
+    let bounds = (1000, 750);
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    group.throughput(Throughput::Elements((bounds.0 * bounds.1) as u64));
+    for (view_name, (upper_left, lower_right)) in [("full", FULL_VIEW), ("seahorse", SEAHORSE_VIEW)]
+    {
+        group.bench_function(view_name, |b| {
+            b.iter(|| render_subdivide(&mut pixels, Band::whole(bounds, upper_left, lower_right)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_threads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render_bands");
+    group.sample_size(10);
+    let bounds = (1000, 750);
+    let (upper_left, lower_right) = SEAHORSE_VIEW;
+    let max_threads = thread::available_parallelism().map_or(8, |n| n.get());
+
+    // 1, 2, 4, ... 直到机器的并行度
+    let mut thread_counts: Vec<usize> = (0..)
+        .map(|i| 1 << i)
+        .take_while(|&n| n < max_threads)
+        .collect();
+    thread_counts.push(max_threads);
+
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    group.throughput(Throughput::Elements((bounds.0 * bounds.1) as u64));
+    for threads in thread_counts {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| {
+                    render_bands(
+                        &mut pixels,
+                        bounds,
+                        upper_left,
+                        lower_right,
+                        threads,
+                        render_band,
+                    )
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_escape_time,
+    bench_render,
+    bench_subdivide,
+    bench_threads
+);
+criterion_main!(benches);
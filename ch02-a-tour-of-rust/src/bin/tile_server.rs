@@ -0,0 +1,100 @@
+//! 本地瓦片服务器：在浏览器里浏览曼德勃罗集。
+//!
+//! 运行`cargo run --release --bin tile_server [PORT]`，然后打开
+//! `http://127.0.0.1:8080/`。瓦片按`/z/x/y.png`的路径提供。
+
+use ch02::concurrency::tiles::{TileCache, parse_tile_path, render_tile};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::{env, thread};
+
+/// 内存中最多缓存的瓦片数。
+const CACHE_CAPACITY: usize = 4096;
+
+const VIEWER_HTML: &str = include_str!("viewer.html");
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let port: u16 = match args.get(1) {
+        Some(port) => port.parse().expect("error parsing port"),
+        None => 8080,
+    };
+
+    // 只监听本机
+    let listener = TcpListener::bind(("127.0.0.1", port)).expect("error binding port");
+    println!("Serving on http://127.0.0.1:{}/", port);
+
+    let cache = Arc::new(Mutex::new(TileCache::new(CACHE_CAPACITY)));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("error accepting connection: {}", err);
+                continue;
+            }
+        };
+        let cache = Arc::clone(&cache);
+        thread::spawn(move || {
+            if let Err(err) = handle(stream, &cache) {
+                eprintln!("error handling request: {}", err);
+            }
+        });
+    }
+}
+
+/// 处理一个 HTTP 请求：`/`返回查看器页面，`/z/x/y.png`返回瓦片。
+fn handle(stream: TcpStream, cache: &Mutex<TileCache>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // 读完请求头，剩下的内容我们不关心
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut stream = reader.into_inner();
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next(), parts.next().unwrap_or(""));
+    if method != Some("GET") {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+    }
+    if path == "/" {
+        return respond(&mut stream, "200 OK", "text/html", VIEWER_HTML.as_bytes());
+    }
+
+    let Some(tile) = parse_tile_path(path) else {
+        return respond(&mut stream, "404 Not Found", "text/plain", b"not found");
+    };
+    // 渲染时不持有锁，这样多块瓦片可以同时渲染
+    let cached = cache.lock().unwrap().get(tile);
+    let png = match cached {
+        Some(png) => png,
+        None => match render_tile(tile) {
+            Some(png) => {
+                cache.lock().unwrap().insert(tile, png.clone());
+                png
+            }
+            None => return respond(&mut stream, "404 Not Found", "text/plain", b"no such tile"),
+        },
+    };
+    respond(&mut stream, "200 OK", "image/png", &png)
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
@@ -0,0 +1,325 @@
+use crate::concurrency::buddhabrot::{batch_seed, in_cardioid_or_bulb};
+use crate::concurrency::draw::render_bands;
+use crate::concurrency::mandelbrot::escape_time;
+use num::Complex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// 目前已知的曼德勃罗集面积的估计值（Förstemann, 2012）。
+pub const KNOWN_AREA: f64 = 1.506_591_88;
+
+/// 每一批随机采样的数量。和`buddhabrot`一样，每批使用由种子和批号决定的
+/// 随机数生成器，所以结果和线程数无关。
+pub const SAMPLES_PER_BATCH: u64 = 10_000;
+
+/// 95% 置信区间对应的正态分布分位数。
+const Z_95: f64 = 1.96;
+
+/// 采样区域是上半平面的`[-2, 0.5] × [0, 1.25]`，它包含了集合的上半部分。
+/// 集合关于实轴对称，所以面积按两倍计算。
+const REGION_UPPER_LEFT: Complex<f64> = Complex { re: -2.0, im: 1.25 };
+const REGION_WIDTH: f64 = 2.5;
+const REGION_HEIGHT: f64 = 1.25;
+
+/// 整个采样区域（上下两半）的面积。
+fn region_area() -> f64 {
+    2.0 * REGION_WIDTH * REGION_HEIGHT
+}
+
+/// 判断`c`是否在集合里。主心形和周期为 2 的圆盘里的点不用迭代。
+///
+/// 迭代上限有限，所以在上限之内还没有逃逸的点也算在集合里，
+/// 估计出的面积会稍微偏大，上限越大偏差越小。
+fn is_inside(c: Complex<f64>, limit: usize) -> bool {
+    in_cardioid_or_bulb(c) || escape_time(c, limit).is_none()
+}
+
+/// 面积的估计值和误差范围：真正的面积（在给定的迭代上限下）落在
+/// `area - margin`到`area + margin`之间。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AreaEstimate {
+    pub area: f64,
+    pub margin: f64,
+}
+
+impl AreaEstimate {
+    /// 区间的下界和上界。
+    pub fn interval(&self) -> (f64, f64) {
+        (self.area - self.margin, self.area + self.margin)
+    }
+
+    /// `value`是否落在区间里。
+    pub fn contains(&self, value: f64) -> bool {
+        let (low, high) = self.interval();
+        (low..=high).contains(&value)
+    }
+}
+
+/// 例如`1.50712 ± 0.00231`。
+impl fmt::Display for AreaEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.5} ± {:.5}", self.area, self.margin)
+    }
+}
+
+/// 用网格计数估计面积：每单位长度`resolution`个格子，数一数中心在集合里的格子。
+///
+/// 格子按行分成条带，用`threads`个线程计算。误差范围不是统计意义上的置信区间，
+/// 而是“边界格子”（和相邻格子一个在集合里、一个不在）的总面积：集合的边界
+/// 只能穿过这些格子，除非有比格子还小的细节。
+pub fn grid_area(resolution: usize, limit: usize, threads: usize) -> AreaEstimate {
+    let bounds = (
+        (REGION_WIDTH * resolution as f64).round() as usize,
+        (REGION_HEIGHT * resolution as f64).round() as usize,
+    );
+    let cell = Complex {
+        re: REGION_WIDTH / bounds.0 as f64,
+        im: REGION_HEIGHT / bounds.1 as f64,
+    };
+    // 平移半个格子，让每个像素的左上角正好是格子的中心
+    let upper_left = REGION_UPPER_LEFT + Complex::new(cell.re, -cell.im) / 2.0;
+    let lower_right = upper_left + Complex::new(REGION_WIDTH, -REGION_HEIGHT);
+
+    let mut inside = vec![false; bounds.0 * bounds.1];
+    render_bands(
+        &mut inside,
+        bounds,
+        upper_left,
+        lower_right,
+        threads,
+        |pixels, band| {
+            for row in band.top..band.top + band.rows {
+                for column in 0..bounds.0 {
+                    let c =
+                        upper_left + Complex::new(column as f64 * cell.re, -(row as f64) * cell.im);
+                    pixels[(row - band.top) * bounds.0 + column] = is_inside(c, limit);
+                }
+            }
+        },
+    );
+
+    let count = inside.iter().filter(|&&inside| inside).count();
+    let mut boundary = 0;
+    for row in 0..bounds.1 {
+        for column in 0..bounds.0 {
+            let here = inside[row * bounds.0 + column];
+            let differs = (column + 1 < bounds.0 && inside[row * bounds.0 + column + 1] != here)
+                || (column > 0 && inside[row * bounds.0 + column - 1] != here)
+                || (row + 1 < bounds.1 && inside[(row + 1) * bounds.0 + column] != here)
+                || (row > 0 && inside[(row - 1) * bounds.0 + column] != here);
+            if differs {
+                boundary += 1;
+            }
+        }
+    }
+
+    let cell_area = 2.0 * cell.re * cell.im;
+    AreaEstimate {
+        area: count as f64 * cell_area,
+        margin: boundary as f64 * cell_area,
+    }
+}
+
+/// 可以分多次运行、中途保存的蒙特卡罗面积估计。
+///
+/// 状态只有已经完成的批数和落在集合里的样本数，第`k`批总是使用由`seed`
+/// 和`k`决定的随机数，所以从检查点继续运行和一次运行完的结果完全相同。
+/// `Display`和`FromStr`使用和场景文件一样的`key = value`格式，可以直接
+/// 当作检查点文件。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MonteCarlo {
+    pub seed: u64,
+    pub limit: usize,
+    /// 已经完成的批数。
+    pub batches: u64,
+    /// 已经完成的样本里落在集合里的个数。
+    pub inside: u64,
+}
+
+impl MonteCarlo {
+    pub fn new(seed: u64, limit: usize) -> MonteCarlo {
+        MonteCarlo {
+            seed,
+            limit,
+            batches: 0,
+            inside: 0,
+        }
+    }
+
+    /// 已经完成的样本数。
+    pub fn samples(&self) -> u64 {
+        self.batches * SAMPLES_PER_BATCH
+    }
+
+    /// 用`threads`个线程再运行`batches`批采样。
+    pub fn run(&mut self, batches: u64, threads: usize) {
+        let first = self.batches;
+        let end = first + batches;
+        let next_batch = AtomicU64::new(first);
+        let inside = AtomicU64::new(0);
+        let this = &*self;
+
+        crossbeam::scope(|spawner| {
+            for _ in 0..threads.max(1) {
+                spawner.spawn(|_| {
+                    loop {
+                        let batch = next_batch.fetch_add(1, Ordering::Relaxed);
+                        if batch >= end {
+                            break;
+                        }
+                        inside.fetch_add(this.sample_batch(batch), Ordering::Relaxed);
+                    }
+                });
+            }
+        })
+        .expect("error joining threads");
+
+        self.batches = end;
+        self.inside += inside.into_inner();
+    }
+
+    /// 第`batch`批采样中落在集合里的样本数。
+    fn sample_batch(&self, batch: u64) -> u64 {
+        let mut rng = StdRng::seed_from_u64(batch_seed(self.seed, batch));
+        let mut inside = 0;
+        for _ in 0..SAMPLES_PER_BATCH {
+            let c = REGION_UPPER_LEFT
+                + Complex {
+                    re: rng.gen_range(0.0..REGION_WIDTH),
+                    im: -rng.gen_range(0.0..REGION_HEIGHT),
+                };
+            if is_inside(c, self.limit) {
+                inside += 1;
+            }
+        }
+        inside
+    }
+
+    /// 目前的估计值和 95% 置信区间。样本落在集合里的个数服从二项分布，
+    /// 样本数很多时用正态近似。
+    pub fn estimate(&self) -> AreaEstimate {
+        let samples = self.samples();
+        if samples == 0 {
+            return AreaEstimate {
+                area: 0.0,
+                margin: f64::INFINITY,
+            };
+        }
+        let p = self.inside as f64 / samples as f64;
+        AreaEstimate {
+            area: region_area() * p,
+            margin: Z_95 * region_area() * (p * (1.0 - p) / samples as f64).sqrt(),
+        }
+    }
+}
+
+impl FromStr for MonteCarlo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MonteCarlo, String> {
+        let mut values = [None; 4];
+        let keys = ["seed", "limit", "batches", "inside"];
+        for (number, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected `key = value`, found {:?}", line)))?;
+            let index = keys
+                .iter()
+                .position(|k| *k == key.trim())
+                .ok_or_else(|| error(format!("unknown key {:?}", key.trim())))?;
+            let value: u64 = value
+                .trim()
+                .parse()
+                .map_err(|_| error(format!("invalid number {:?}", value.trim())))?;
+            values[index] = Some(value);
+        }
+
+        let get = |index: usize| values[index].ok_or_else(|| format!("missing `{}`", keys[index]));
+        Ok(MonteCarlo {
+            seed: get(0)?,
+            limit: get(1)? as usize,
+            batches: get(2)?,
+            inside: get(3)?,
+        })
+    }
+}
+
+/// 输出`from_str`能解析的检查点。
+impl fmt::Display for MonteCarlo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "seed = {}", self.seed)?;
+        writeln!(f, "limit = {}", self.limit)?;
+        writeln!(f, "batches = {}", self.batches)?;
+        writeln!(f, "inside = {}", self.inside)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid_area() {
+        let estimate = grid_area(100, 1000, 4);
+        assert!(estimate.contains(KNOWN_AREA), "{}", estimate);
+        assert!(estimate.margin < 0.5);
+        // 线程数不影响结果
+        assert_eq!(grid_area(40, 200, 1), grid_area(40, 200, 3));
+    }
+
+    #[test]
+    fn test_monte_carlo() {
+        let mut monte_carlo = MonteCarlo::new(1, 1000);
+        monte_carlo.run(10, 4);
+        assert_eq!(monte_carlo.samples(), 100_000);
+        let estimate = monte_carlo.estimate();
+        // 有限的迭代上限让估计值偏大一点，仍应该离已知值不远
+        assert!((estimate.area - KNOWN_AREA).abs() < 3.0 * estimate.margin + 0.01);
+        assert!(estimate.margin < 0.05);
+    }
+
+    #[test]
+    fn test_monte_carlo_resumes_from_checkpoint() {
+        let mut whole = MonteCarlo::new(5, 100);
+        whole.run(4, 3);
+
+        let mut first = MonteCarlo::new(5, 100);
+        first.run(1, 1);
+        let mut resumed: MonteCarlo = first.to_string().parse().unwrap();
+        assert_eq!(resumed, first);
+        resumed.run(3, 2);
+        assert_eq!(resumed, whole);
+    }
+
+    #[test]
+    fn test_parse_checkpoint_errors() {
+        assert_eq!(
+            "seed = 1\nlimit = 10\nbatches = 2".parse::<MonteCarlo>(),
+            Err("missing `inside`".to_string())
+        );
+        assert_eq!(
+            "seed = x".parse::<MonteCarlo>(),
+            Err("line 1: invalid number \"x\"".to_string())
+        );
+        assert!("samples = 1".parse::<MonteCarlo>().is_err());
+    }
+
+    #[test]
+    fn test_estimate_display() {
+        let estimate = AreaEstimate {
+            area: 1.5,
+            margin: 0.25,
+        };
+        assert_eq!(estimate.to_string(), "1.50000 ± 0.25000");
+        assert_eq!(estimate.interval(), (1.25, 1.75));
+        assert!(!estimate.contains(1.0));
+    }
+}
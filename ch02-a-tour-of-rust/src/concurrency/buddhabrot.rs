@@ -0,0 +1,238 @@
+use crate::concurrency::parse::point_to_pixel;
+use num::Complex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// 每一批采样的数量。每批使用由种子和批号决定的随机数生成器，
+/// 所以结果只取决于种子和采样总数，和线程数无关。
+const SAMPLES_PER_BATCH: usize = 10_000;
+
+/// Buddhabrot 的采样参数。
+#[derive(Clone, Copy, Debug)]
+pub struct Sampling {
+    /// R、G、B 三个通道的迭代上限，参见`accumulate`。
+    pub limits: [usize; 3],
+    /// 随机采样的`c`的总数。
+    pub samples: usize,
+    /// 随机数种子。
+    pub seed: u64,
+}
+
+/// 三个颜色通道（R、G、B）的轨迹密度缓冲区，可以被多个线程同时累加。
+pub struct Density {
+    bounds: (usize, usize),
+    channels: [Vec<AtomicU32>; 3],
+}
+
+impl Density {
+    /// 创建一个宽和高由`bounds`指定、所有计数都为零的密度缓冲区。
+    pub fn new(bounds: (usize, usize)) -> Density {
+        let channel = || {
+            (0..bounds.0 * bounds.1)
+                .map(|_| AtomicU32::new(0))
+                .collect()
+        };
+        Density {
+            bounds,
+            channels: [channel(), channel(), channel()],
+        }
+    }
+
+    /// 返回通道`channel`中像素`pixel`（列，行）的计数。
+    pub fn count(&self, channel: usize, pixel: (usize, usize)) -> u32 {
+        self.channels[channel][pixel.1 * self.bounds.0 + pixel.0].load(Ordering::Relaxed)
+    }
+
+    fn record(&self, channel: usize, pixel: (usize, usize)) {
+        self.channels[channel][pixel.1 * self.bounds.0 + pixel.0].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 把密度映射到 RGB 缓冲区`pixels`里，每个像素占三个字节。
+    ///
+    /// 每个通道单独按自己的最大计数归一化，再取平方根，
+    /// 这样稀疏的轨迹也能看得见。
+    pub fn tone_map(&self, pixels: &mut [u8]) {
+        assert_eq!(pixels.len(), self.bounds.0 * self.bounds.1 * 3);
+
+        for (channel, counts) in self.channels.iter().enumerate() {
+            let max = counts
+                .iter()
+                .map(|count| count.load(Ordering::Relaxed))
+                .max()
+                .unwrap_or(0);
+            if max == 0 {
+                continue;
+            }
+            for (i, count) in counts.iter().enumerate() {
+                let t = count.load(Ordering::Relaxed) as f64 / max as f64;
+                pixels[i * 3 + channel] = (t.sqrt() * 255.0) as u8;
+            }
+        }
+    }
+}
+
+/// 在区域`|re| <= 2, |im| <= 2`内随机采样`samples`个`c`，把每个逃逸点的
+/// 轨迹累加到`density`里。
+///
+/// `limits`分别是 R、G、B 三个通道的迭代上限：只有在某个通道的上限之内
+/// 逃逸的点，它的轨迹才会被记入这个通道（Nebulabrot）。三个上限相同时
+/// 就是普通的 Buddhabrot。`upper_left`和`lower_right`指定了缓冲区覆盖的
+/// 复平面区域。
+pub fn accumulate<R: Rng>(
+    density: &Density,
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    limits: [usize; 3],
+    samples: usize,
+    rng: &mut R,
+) {
+    let limit = limits.iter().copied().max().unwrap_or(0);
+    let mut orbit = Vec::with_capacity(limit);
+    for _ in 0..samples {
+        let c = Complex {
+            re: rng.gen_range(-2.0..2.0),
+            im: rng.gen_range(-2.0..2.0),
+        };
+        // 主心形和周期为 2 的圆盘里的点永远不会逃逸，直接跳过
+        if in_cardioid_or_bulb(c) {
+            continue;
+        }
+
+        orbit.clear();
+        let mut z = Complex { re: 0.0, im: 0.0 };
+        let mut escaped = None;
+        // 只记录逃逸之前（仍在半径为 2 的圆内）的轨迹点
+        for i in 1..=limit {
+            z = z * z + c;
+            if z.norm_sqr() > 4.0 {
+                escaped = Some(i);
+                break;
+            }
+            orbit.push(z);
+        }
+        let Some(steps) = escaped else { continue };
+
+        for (channel, &channel_limit) in limits.iter().enumerate() {
+            if steps > channel_limit {
+                continue;
+            }
+            for &point in &orbit {
+                if let Some(pixel) = point_to_pixel(density.bounds, point, upper_left, lower_right)
+                {
+                    density.record(channel, pixel);
+                }
+            }
+        }
+    }
+}
+
+/// 用`threads`个线程渲染 Buddhabrot，结果写入 RGB 缓冲区`pixels`。
+///
+/// 采样被分成固定大小的批次，线程轮流领取批次并累加到同一个`Density`里。
+/// 相同的`sampling`总是产生相同的图像。
+pub fn render_buddhabrot(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    sampling: Sampling,
+    threads: usize,
+) {
+    let Sampling {
+        limits,
+        samples,
+        seed,
+    } = sampling;
+    let density = Density::new(bounds);
+    let batches = samples.div_ceil(SAMPLES_PER_BATCH);
+    let next_batch = AtomicUsize::new(0);
+
+    crossbeam::scope(|spawner| {
+        for _ in 0..threads {
+            spawner.spawn(|_| {
+                loop {
+                    let batch = next_batch.fetch_add(1, Ordering::Relaxed);
+                    if batch >= batches {
+                        break;
+                    }
+                    let count = SAMPLES_PER_BATCH.min(samples - batch * SAMPLES_PER_BATCH);
+                    let mut rng = StdRng::seed_from_u64(batch_seed(seed, batch as u64));
+                    accumulate(&density, upper_left, lower_right, limits, count, &mut rng);
+                }
+            });
+        }
+    })
+    .expect("error joining threads");
+
+    density.tone_map(pixels);
+}
+
+/// 种子为`seed`时第`batch`批采样的随机数种子。
+///
+/// 用 splitmix64 把种子和批号充分混合。直接相加的话，种子`s`的第`k`批
+/// 就是种子`s + 1`的第`k - 1`批，相邻种子的结果几乎相同。
+pub(crate) fn batch_seed(seed: u64, batch: u64) -> u64 {
+    let mut z = seed ^ batch.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// 判断`c`是否在主心形或者周期为 2 的圆盘里。
+pub(crate) fn in_cardioid_or_bulb(c: Complex<f64>) -> bool {
+    let q = (c.re - 0.25) * (c.re - 0.25) + c.im * c.im;
+    q * (q + (c.re - 0.25)) <= 0.25 * c.im * c.im || (c.re + 1.0).powi(2) + c.im * c.im <= 0.0625
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_in_cardioid_or_bulb() {
+        assert!(in_cardioid_or_bulb(Complex { re: 0.0, im: 0.0 }));
+        assert!(in_cardioid_or_bulb(Complex { re: -1.0, im: 0.0 }));
+        assert!(!in_cardioid_or_bulb(Complex { re: 0.5, im: 0.0 }));
+        assert!(!in_cardioid_or_bulb(Complex { re: -1.5, im: 0.0 }));
+    }
+
+    #[test]
+    fn test_batch_seed() {
+        // 相邻的种子不共享任何一批
+        let seeds = |seed| (0..1000).map(move |batch| batch_seed(seed, batch));
+        let first: HashSet<u64> = seeds(7).collect();
+        assert_eq!(first.len(), 1000);
+        assert!(seeds(8).all(|seed| !first.contains(&seed)));
+        assert_eq!(batch_seed(7, 3), batch_seed(7, 3));
+    }
+
+    #[test]
+    fn test_render_buddhabrot_is_reproducible() {
+        let bounds = (32, 32);
+        let upper_left = Complex { re: -2.0, im: 2.0 };
+        let lower_right = Complex { re: 2.0, im: -2.0 };
+        let render = |seed, threads| {
+            let mut pixels = vec![0; bounds.0 * bounds.1 * 3];
+            render_buddhabrot(
+                &mut pixels,
+                bounds,
+                upper_left,
+                lower_right,
+                Sampling {
+                    limits: [50, 20, 10],
+                    samples: 25_000,
+                    seed,
+                },
+                threads,
+            );
+            pixels
+        };
+
+        let pixels = render(7, 4);
+        assert!(pixels.iter().any(|&p| p > 0));
+        assert_eq!(pixels, render(7, 1));
+        assert_ne!(pixels, render(8, 4));
+    }
+}
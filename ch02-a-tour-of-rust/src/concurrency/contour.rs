@@ -0,0 +1,340 @@
+use crate::concurrency::palette::hsv;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// 像素坐标里的一条折线。
+pub type Polyline = Vec<(f64, f64)>;
+
+/// 要提取的一条等值线和它的颜色。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Level {
+    /// 迭代次数。
+    pub value: f64,
+    /// 没有指定颜色时，`write_svg`从色环上给每个级别挑一个颜色。
+    pub color: Option<[u8; 3]>,
+}
+
+/// 形如`100`或者`100:#ff8000`。
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Level, String> {
+        let (value, color) = match s.split_once(':') {
+            Some((value, color)) => (value, Some(color)),
+            None => (s, None),
+        };
+        let value = value
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid iteration count {:?}", value.trim()))?;
+        let color =
+            match color {
+                Some(color) => Some(parse_color(color.trim()).ok_or_else(|| {
+                    format!("invalid color {:?}, expected `#rrggbb`", color.trim())
+                })?),
+                None => None,
+            };
+        Ok(Level { value, color })
+    }
+}
+
+/// 解析`#rrggbb`形式的颜色。
+fn parse_color(s: &str) -> Option<[u8; 3]> {
+    let hex = s.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// 网格上的一条边：从格点（`x`，`y`）向右（`vertical`为假）或者向下的一条边。
+/// 等值线和每条边最多相交一次，所以可以用边来标识交点。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Edge {
+    x: usize,
+    y: usize,
+    vertical: bool,
+}
+
+/// 用 marching squares 提取按行排列的`values`里值等于`level`的等值线。
+///
+/// 每个像素是一个格点，坐标是像素的中心（`column + 0.5`，`row + 0.5`），
+/// 和 PNG 图像里的位置对齐。交点在相邻格点之间线性插值。返回的折线已经
+/// 首尾相连地拼接好了，封闭的折线最后一个点和第一个点相同。
+///
+/// 值大于等于`level`的格点算在等值线“里面”。鞍点（对角的两个格点在里面）
+/// 用格子中心的平均值决定怎样连接。
+pub fn marching_squares(values: &[f64], bounds: (usize, usize), level: f64) -> Vec<Polyline> {
+    assert_eq!(values.len(), bounds.0 * bounds.1);
+
+    let value = |x: usize, y: usize| values[y * bounds.0 + x];
+    let mut segments: Vec<(Edge, Edge)> = Vec::new();
+    for y in 0..bounds.1.saturating_sub(1) {
+        for x in 0..bounds.0.saturating_sub(1) {
+            let corners = [
+                value(x, y),
+                value(x + 1, y),
+                value(x + 1, y + 1),
+                value(x, y + 1),
+            ];
+            let case = corners
+                .iter()
+                .fold(0, |case, &v| case << 1 | (v >= level) as usize);
+            let top = Edge {
+                x,
+                y,
+                vertical: false,
+            };
+            let right = Edge {
+                x: x + 1,
+                y,
+                vertical: true,
+            };
+            let bottom = Edge {
+                x,
+                y: y + 1,
+                vertical: false,
+            };
+            let left = Edge {
+                x,
+                y,
+                vertical: true,
+            };
+            let center = corners.iter().sum::<f64>() / 4.0 >= level;
+            // 四个角按左上、右上、右下、左下的顺序从高位到低位
+            match case {
+                0b0001 | 0b1110 => segments.push((left, bottom)),
+                0b0010 | 0b1101 => segments.push((bottom, right)),
+                0b0011 | 0b1100 => segments.push((left, right)),
+                0b0100 | 0b1011 => segments.push((top, right)),
+                0b0110 | 0b1001 => segments.push((top, bottom)),
+                0b0111 | 0b1000 => segments.push((left, top)),
+                // 右上和左下在里面
+                0b0101 if center => {
+                    segments.push((left, top));
+                    segments.push((bottom, right));
+                }
+                0b0101 => {
+                    segments.push((left, bottom));
+                    segments.push((top, right));
+                }
+                // 左上和右下在里面
+                0b1010 if center => {
+                    segments.push((left, bottom));
+                    segments.push((top, right));
+                }
+                0b1010 => {
+                    segments.push((left, top));
+                    segments.push((bottom, right));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let point = |edge: Edge| {
+        let (x, y) = (edge.x, edge.y);
+        let (a, b) = if edge.vertical {
+            (value(x, y), value(x, y + 1))
+        } else {
+            (value(x, y), value(x + 1, y))
+        };
+        let t = if a == b { 0.5 } else { (level - a) / (b - a) };
+        if edge.vertical {
+            (x as f64 + 0.5, y as f64 + 0.5 + t)
+        } else {
+            (x as f64 + 0.5 + t, y as f64 + 0.5)
+        }
+    };
+
+    // 每条边最多被两个相邻格子里的线段用到
+    let mut by_edge: HashMap<Edge, Vec<usize>> = HashMap::new();
+    for (i, &(a, b)) in segments.iter().enumerate() {
+        by_edge.entry(a).or_default().push(i);
+        by_edge.entry(b).or_default().push(i);
+    }
+    let open_end = |edge: Edge| by_edge[&edge].len() == 1;
+
+    let mut used = vec![false; segments.len()];
+    let mut paths = Vec::new();
+    // 先从图像边界上的端点开始走完不封闭的折线，剩下的都是封闭的
+    let starts = (0..segments.len())
+        .filter(|&i| open_end(segments[i].0) || open_end(segments[i].1))
+        .chain(0..segments.len());
+    for start in starts {
+        if used[start] {
+            continue;
+        }
+        used[start] = true;
+        let (a, b) = segments[start];
+        let (from, mut current) = if open_end(b) && !open_end(a) {
+            (b, a)
+        } else {
+            (a, b)
+        };
+        let mut path = vec![point(from), point(current)];
+        while let Some(&next) = by_edge[&current].iter().find(|&&i| !used[i]) {
+            used[next] = true;
+            let (a, b) = segments[next];
+            current = if a == current { b } else { a };
+            path.push(point(current));
+        }
+        paths.push(path);
+    }
+    paths
+}
+
+/// 把每个等值线级别的折线写成 SVG，`contours`里每一项是一个级别和它的折线。
+///
+/// 图像的`viewBox`是像素坐标，和`write_image`输出的 PNG 对齐，
+/// 放大到任何尺寸都保持清晰。每个级别是一个`path`元素。
+pub fn write_svg<W: Write>(
+    mut output: W,
+    bounds: (usize, usize),
+    contours: &[(Level, Vec<Polyline>)],
+    stroke_width: f64,
+) -> io::Result<()> {
+    writeln!(
+        output,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {w} {h}\" width=\"{w}\" height=\"{h}\">",
+        w = bounds.0,
+        h = bounds.1
+    )?;
+    writeln!(
+        output,
+        "<g fill=\"none\" stroke-width=\"{}\" stroke-linejoin=\"round\" stroke-linecap=\"round\">",
+        stroke_width
+    )?;
+    for (i, (level, paths)) in contours.iter().enumerate() {
+        let hue = i as f64 / contours.len() as f64;
+        let [r, g, b] = level.color.unwrap_or_else(|| hsv(hue, 0.8, 0.8));
+        write!(
+            output,
+            "<path data-level=\"{}\" stroke=\"#{:02x}{:02x}{:02x}\" d=\"",
+            level.value, r, g, b
+        )?;
+        for (index, path) in paths.iter().enumerate() {
+            if index > 0 {
+                write!(output, " ")?;
+            }
+            let closed = path.len() > 2 && path.first() == path.last();
+            let points = if closed {
+                &path[..path.len() - 1]
+            } else {
+                &path[..]
+            };
+            for (j, (x, y)) in points.iter().enumerate() {
+                let command = if j == 0 { "M" } else { "L" };
+                write!(output, "{}{:.2} {:.2}", command, x, y)?;
+            }
+            if closed {
+                write!(output, "Z")?;
+            }
+        }
+        writeln!(output, "\"/>")?;
+    }
+    writeln!(output, "</g>")?;
+    writeln!(output, "</svg>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 以（`cx`，`cy`）为中心的锥形，中心的值最大。
+    fn cone(bounds: (usize, usize), cx: f64, cy: f64) -> Vec<f64> {
+        let mut values = Vec::new();
+        for y in 0..bounds.1 {
+            for x in 0..bounds.0 {
+                let (dx, dy) = (x as f64 - cx, y as f64 - cy);
+                values.push(10.0 - (dx * dx + dy * dy).sqrt());
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(
+            "100:#ff8000".parse(),
+            Ok(Level {
+                value: 100.0,
+                color: Some([255, 128, 0])
+            })
+        );
+        assert_eq!(
+            "2.5".parse(),
+            Ok(Level {
+                value: 2.5,
+                color: None
+            })
+        );
+        assert!("x:#000000".parse::<Level>().is_err());
+        assert!("1:#12345".parse::<Level>().is_err());
+        assert!("1:red".parse::<Level>().is_err());
+    }
+
+    #[test]
+    fn test_closed_contour() {
+        let values = cone((21, 21), 10.0, 10.0);
+        let paths = marching_squares(&values, (21, 21), 5.0);
+        assert_eq!(paths.len(), 1);
+        let path = &paths[0];
+        assert_eq!(path.first(), path.last());
+        // 等值线是以像素（10，10）的中心为圆心、半径 5 的圆
+        for &(x, y) in path {
+            let r = ((x - 10.5).powi(2) + (y - 10.5).powi(2)).sqrt();
+            assert!((r - 5.0).abs() < 0.1, "{}", r);
+        }
+    }
+
+    #[test]
+    fn test_open_contours() {
+        // 圆心在图像的左边界上，等值线是一段被边界截断的圆弧
+        let values = cone((15, 21), 0.0, 10.0);
+        let paths = marching_squares(&values, (15, 21), 5.0);
+        assert_eq!(paths.len(), 1);
+        let path = &paths[0];
+        assert_ne!(path.first(), path.last());
+        assert_eq!(path[0].0, 0.5);
+        assert_eq!(path[path.len() - 1].0, 0.5);
+
+        // 高于所有值的级别没有等值线
+        assert!(marching_squares(&values, (15, 21), 100.0).is_empty());
+    }
+
+    #[test]
+    fn test_saddle() {
+        // 对角的两个格点在里面，两种连接方式都只生成两条互不相交的线段
+        for values in [[1.0, 0.0, 0.0, 1.0], [1.0, 0.0, 0.0, 0.9]] {
+            let paths = marching_squares(&values, (2, 2), 0.5);
+            assert_eq!(paths.len(), 2);
+            assert!(paths.iter().all(|path| path.len() == 2));
+        }
+    }
+
+    #[test]
+    fn test_write_svg() {
+        let level = Level {
+            value: 3.0,
+            color: Some([255, 0, 16]),
+        };
+        let contours = vec![(
+            level,
+            vec![
+                vec![(0.5, 0.5), (1.0, 1.0)],
+                vec![(1.0, 1.0), (2.0, 1.0), (2.0, 2.0), (1.0, 1.0)],
+            ],
+        )];
+        let mut svg = Vec::new();
+        write_svg(&mut svg, (4, 3), &contours, 0.5).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 4 3\""));
+        assert!(svg.contains(
+            "<path data-level=\"3\" stroke=\"#ff0010\" d=\"M0.50 0.50L1.00 1.00 M1.00 1.00L2.00 1.00L2.00 2.00Z\"/>"
+        ));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+}
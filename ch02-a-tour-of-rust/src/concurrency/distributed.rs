@@ -0,0 +1,495 @@
+use crate::concurrency::draw::render_window;
+use num::Complex;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// 默认的瓦片边长（像素）。
+pub const DEFAULT_TILE_SIZE: usize = 128;
+
+/// 默认等待工作进程交回一块瓦片的时间，超过这个时间就认为它失败了。
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 工作进程回复的`pixels N`一行最多读这么多字节。
+const MAX_REPLY_LINE: u64 = 64;
+
+/// 协调者等待新连接时检查图像是否已经完成的间隔。
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// 图像里的一块矩形：左上角的列和行，以及宽和高。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub left: usize,
+    pub top: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// 把`bounds`大小的图像切成边长最多为`size`的瓦片，按行排列。
+pub fn split_tiles(bounds: (usize, usize), size: usize) -> Vec<Tile> {
+    let size = size.max(1);
+    let mut tiles = Vec::new();
+    for top in (0..bounds.1).step_by(size) {
+        for left in (0..bounds.0).step_by(size) {
+            tiles.push(Tile {
+                left,
+                top,
+                width: size.min(bounds.0 - left),
+                height: size.min(bounds.1 - top),
+            });
+        }
+    }
+    tiles
+}
+
+/// 一次渲染任务：整幅图像的宽和高，以及它覆盖的复平面区域。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Job {
+    pub bounds: (usize, usize),
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>,
+}
+
+impl Job {
+    /// 用`threads`个线程渲染瓦片`tile`，返回按行排列的灰度像素。
+    ///
+    /// 每个像素都按它在整幅图像里的位置计算，所以结果和瓦片大小、线程数都无关，
+    /// 拼起来和对整幅图像调用`render`完全相同。不同机器上的工作进程可以混用。
+    pub fn render_tile(&self, tile: Tile, threads: usize) -> Vec<u8> {
+        let mut pixels = vec![0; tile.width * tile.height];
+        if pixels.is_empty() {
+            return pixels;
+        }
+        let rows = tile.height.div_ceil(threads.max(1));
+        crossbeam::scope(|spawner| {
+            for (i, band) in pixels.chunks_mut(rows * tile.width).enumerate() {
+                spawner.spawn(move |_| {
+                    render_window(
+                        band,
+                        self.bounds,
+                        self.upper_left,
+                        self.lower_right,
+                        (tile.left, tile.top + i * rows),
+                        (tile.width, band.len() / tile.width),
+                    )
+                });
+            }
+        })
+        .expect("error joining threads");
+        pixels
+    }
+}
+
+/// 协调者发给工作进程的一行消息。
+///
+/// 每条`render`消息都带着整个任务，工作进程不需要记住任何状态。
+/// 浮点数用`Display`格式写出，解析回来和原来的值完全相同。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Request {
+    /// `render W H UL_RE UL_IM LR_RE LR_IM LEFT TOP WIDTH HEIGHT`：渲染一块瓦片。
+    Render(Job, Tile),
+    /// `done`：图像已经完成，工作进程可以退出了。
+    Done,
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Request::Render(job, tile) => write!(
+                f,
+                "render {} {} {} {} {} {} {} {} {} {}",
+                job.bounds.0,
+                job.bounds.1,
+                job.upper_left.re,
+                job.upper_left.im,
+                job.lower_right.re,
+                job.lower_right.im,
+                tile.left,
+                tile.top,
+                tile.width,
+                tile.height
+            ),
+            Request::Done => write!(f, "done"),
+        }
+    }
+}
+
+impl FromStr for Request {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Request, String> {
+        let mut words = s.split_whitespace();
+        match words.next() {
+            Some("done") if words.next().is_none() => Ok(Request::Done),
+            Some("render") => {
+                let words: Vec<&str> = words.collect();
+                let malformed = || format!("malformed render request {:?}", s);
+                if words.len() != 10 {
+                    return Err(malformed());
+                }
+                let size = |i: usize| words[i].parse::<usize>().map_err(|_| malformed());
+                let float = |i: usize| words[i].parse::<f64>().map_err(|_| malformed());
+                let job = Job {
+                    bounds: (size(0)?, size(1)?),
+                    upper_left: Complex::new(float(2)?, float(3)?),
+                    lower_right: Complex::new(float(4)?, float(5)?),
+                };
+                let tile = Tile {
+                    left: size(6)?,
+                    top: size(7)?,
+                    width: size(8)?,
+                    height: size(9)?,
+                };
+                let inside = |start: usize, length: usize, bound: usize| {
+                    start.checked_add(length).is_some_and(|end| end <= bound)
+                };
+                if !inside(tile.left, tile.width, job.bounds.0)
+                    || !inside(tile.top, tile.height, job.bounds.1)
+                {
+                    return Err(format!("tile outside the image in {:?}", s));
+                }
+                Ok(Request::Render(job, tile))
+            }
+            _ => Err(format!("unknown request {:?}", s)),
+        }
+    }
+}
+
+/// 一次分布式渲染的统计。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// 连接过的工作进程数。
+    pub workers: usize,
+    /// 因为工作进程断开、超时或者发回错误的数据而重新分配的瓦片数。
+    pub reassigned: usize,
+}
+
+/// 所有连接共享的渲染进度。
+struct Progress {
+    /// 还没有分出去的瓦片，失败的瓦片放回这里。
+    pending: VecDeque<Tile>,
+    /// 还没有收到结果的瓦片数，包括正在渲染的。
+    remaining: usize,
+    pixels: Vec<u8>,
+    summary: Summary,
+}
+
+/// 分布式渲染的协调者：把图像切成瓦片分给通过 TCP 连接进来的工作进程，
+/// 再把它们发回的像素拼成整幅图像。
+///
+/// 每个工作进程一次只拿一块瓦片，交回结果后再拿下一块，所以快的进程自然
+/// 分到更多瓦片。连接断开、超过`timeout`没有交回结果、或者发回的数据不对时，
+/// 这块瓦片会放回队列，交给别的工作进程。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Coordinator {
+    pub job: Job,
+    pub tile_size: usize,
+    pub timeout: Duration,
+}
+
+impl Coordinator {
+    pub fn new(job: Job) -> Coordinator {
+        Coordinator {
+            job,
+            tile_size: DEFAULT_TILE_SIZE,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// 接受`listener`上的工作进程，直到整幅图像都渲染完，返回按行排列的
+    /// 灰度像素。工作进程可以在任何时候连接进来；一直没有工作进程时就一直等。
+    pub fn run(&self, listener: &TcpListener) -> io::Result<(Vec<u8>, Summary)> {
+        let tiles = split_tiles(self.job.bounds, self.tile_size);
+        let progress = Mutex::new(Progress {
+            remaining: tiles.len(),
+            pending: tiles.into(),
+            pixels: vec![0; self.job.bounds.0 * self.job.bounds.1],
+            summary: Summary::default(),
+        });
+        let changed = Condvar::new();
+
+        // 非阻塞地接受连接，这样图像完成后能及时停下来
+        listener.set_nonblocking(true)?;
+        thread::scope(|scope| {
+            while progress.lock().unwrap().remaining > 0 {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        progress.lock().unwrap().summary.workers += 1;
+                        let (progress, changed) = (&progress, &changed);
+                        scope.spawn(move || self.serve(stream, progress, changed));
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(ACCEPT_POLL_INTERVAL);
+                    }
+                    // 对方在被接受之前就断开了之类的错误只影响这一个连接
+                    Err(_) => thread::sleep(ACCEPT_POLL_INTERVAL),
+                }
+            }
+        });
+        listener.set_nonblocking(false)?;
+
+        let progress = progress.into_inner().unwrap();
+        Ok((progress.pixels, progress.summary))
+    }
+
+    /// 给一个工作进程分配瓦片，直到没有瓦片可分或者它失败了。
+    fn serve(
+        &self,
+        stream: TcpStream,
+        progress: &Mutex<Progress>,
+        changed: &Condvar,
+    ) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        let mut reader = BufReader::new(Deadline {
+            stream: stream.try_clone()?,
+            deadline: Instant::now(),
+        });
+        let mut writer = stream;
+        loop {
+            let Some(tile) = next_tile(progress, changed) else {
+                return writeln!(writer, "{}", Request::Done);
+            };
+            match self.request(&mut reader, &mut writer, tile) {
+                Ok(pixels) => {
+                    let mut progress = progress.lock().unwrap();
+                    let width = self.job.bounds.0;
+                    for (row, line) in pixels.chunks(tile.width).enumerate() {
+                        let start = (tile.top + row) * width + tile.left;
+                        progress.pixels[start..start + tile.width].copy_from_slice(line);
+                    }
+                    progress.remaining -= 1;
+                }
+                Err(err) => {
+                    let mut progress = progress.lock().unwrap();
+                    progress.pending.push_back(tile);
+                    progress.summary.reassigned += 1;
+                    changed.notify_all();
+                    return Err(err);
+                }
+            }
+            changed.notify_all();
+        }
+    }
+
+    /// 请工作进程渲染`tile`，等它在`timeout`之内发回像素。
+    fn request(
+        &self,
+        reader: &mut BufReader<Deadline>,
+        writer: &mut TcpStream,
+        tile: Tile,
+    ) -> io::Result<Vec<u8>> {
+        writeln!(writer, "{}", Request::Render(self.job, tile))?;
+        reader.get_mut().deadline = Instant::now() + self.timeout;
+        let mut line = String::new();
+        reader.by_ref().take(MAX_REPLY_LINE).read_line(&mut line)?;
+        let expected = tile.width * tile.height;
+        let length = line
+            .trim()
+            .strip_prefix("pixels ")
+            .and_then(|length| length.parse::<usize>().ok());
+        if length != Some(expected) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected `pixels {}`, found {:?}", expected, line.trim()),
+            ));
+        }
+        let mut pixels = vec![0; expected];
+        reader.read_exact(&mut pixels)?;
+        Ok(pixels)
+    }
+}
+
+/// 带截止时间的连接：每次读取都只等到`deadline`为止。
+///
+/// 只设置每次读取的超时的话，每隔一会儿发一个字节的工作进程可以永远占着
+/// 一块瓦片；截止时间限制的是整块瓦片的总时间。
+struct Deadline {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl Read for Deadline {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "worker missed the tile deadline",
+            ));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+/// 取出下一块要渲染的瓦片。队列空了但还有瓦片在别的工作进程手里时就等着，
+/// 它们失败的话瓦片会放回队列。所有瓦片都完成时返回`None`。
+fn next_tile(progress: &Mutex<Progress>, changed: &Condvar) -> Option<Tile> {
+    let mut progress = progress.lock().unwrap();
+    loop {
+        if let Some(tile) = progress.pending.pop_front() {
+            return Some(tile);
+        }
+        if progress.remaining == 0 {
+            return None;
+        }
+        progress = changed.wait(progress).unwrap();
+    }
+}
+
+/// 作为工作进程为`stream`另一端的协调者渲染瓦片，每块瓦片用`threads`个线程。
+///
+/// 每收到一条`render`请求，就回复一行`pixels N`，后面跟着瓦片的`N`个灰度像素。
+/// 收到`done`或者协调者关闭连接时返回渲染过的瓦片数。
+pub fn work(stream: TcpStream, threads: usize) -> io::Result<usize> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let mut rendered = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(rendered);
+        }
+        let request = line
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        match request {
+            Request::Done => return Ok(rendered),
+            Request::Render(job, tile) => {
+                let pixels = job.render_tile(tile, threads);
+                writeln!(writer, "pixels {}", pixels.len())?;
+                writer.write_all(&pixels)?;
+                writer.flush()?;
+                rendered += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrency::draw::render;
+
+    fn job() -> Job {
+        Job {
+            bounds: (50, 30),
+            upper_left: Complex { re: -2.0, im: 1.0 },
+            lower_right: Complex { re: 1.0, im: -1.0 },
+        }
+    }
+
+    #[test]
+    fn test_split_tiles() {
+        let tiles = split_tiles((50, 30), 16);
+        assert_eq!(tiles.len(), 4 * 2);
+        assert_eq!(
+            tiles[3],
+            Tile {
+                left: 48,
+                top: 0,
+                width: 2,
+                height: 16
+            }
+        );
+        // 瓦片不重叠地铺满整幅图像
+        let mut covered = vec![0; 50 * 30];
+        for tile in &tiles {
+            for row in tile.top..tile.top + tile.height {
+                for column in tile.left..tile.left + tile.width {
+                    covered[row * 50 + column] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&count| count == 1));
+        assert!(split_tiles((0, 30), 16).is_empty());
+    }
+
+    #[test]
+    fn test_request() {
+        let tile = Tile {
+            left: 16,
+            top: 0,
+            width: 16,
+            height: 14,
+        };
+        let mut job = job();
+        job.upper_left.re = 0.1 + 0.2;
+        let request = Request::Render(job, tile);
+        assert_eq!(request.to_string().parse(), Ok(request));
+        assert_eq!("done".parse(), Ok(Request::Done));
+
+        assert!("render 1 2 3".parse::<Request>().is_err());
+        assert!("render 10 10 0 0 1 1 8 0 4 4".parse::<Request>().is_err());
+        assert_eq!(
+            "render 10 10 0 0 1 1 18446744073709551615 0 1 1".parse::<Request>(),
+            Err(
+                "tile outside the image in \"render 10 10 0 0 1 1 18446744073709551615 0 1 1\""
+                    .to_string()
+            )
+        );
+        assert!("stop".parse::<Request>().is_err());
+    }
+
+    #[test]
+    fn test_distributed_render() {
+        let job = job();
+        let mut coordinator = Coordinator::new(job);
+        coordinator.tile_size = 8;
+        coordinator.timeout = Duration::from_millis(200);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || coordinator.run(&listener).unwrap());
+
+        // 一个工作进程拿到瓦片后就断开，一个一直不回复，还有一个每隔一会儿
+        // 才发一个字节
+        let request = |stream: &TcpStream| {
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            assert!(line.starts_with("render "), "{:?}", line);
+        };
+        let dropped = TcpStream::connect(address).unwrap();
+        request(&dropped);
+        drop(dropped);
+        let stalled = TcpStream::connect(address).unwrap();
+        request(&stalled);
+        let mut trickling = TcpStream::connect(address).unwrap();
+        request(&trickling);
+        let trickle = thread::spawn(move || {
+            let mut reply = b"pixels 64\n".to_vec();
+            reply.resize(reply.len() + 64, 0);
+            for byte in reply.chunks(1) {
+                if trickling.write_all(byte).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        let workers: Vec<_> = (0..2)
+            .map(|_| thread::spawn(move || work(TcpStream::connect(address).unwrap(), 2).unwrap()))
+            .collect();
+        let (pixels, summary) = handle.join().unwrap();
+        let rendered: usize = workers.into_iter().map(|w| w.join().unwrap()).sum();
+        drop(stalled);
+        trickle.join().unwrap();
+
+        let tiles = split_tiles(job.bounds, 8);
+        assert_eq!(rendered, tiles.len());
+        assert_eq!(
+            summary,
+            Summary {
+                workers: 5,
+                reassigned: 3
+            }
+        );
+        let mut expected = vec![0; job.bounds.0 * job.bounds.1];
+        render(&mut expected, job.bounds, job.upper_left, job.lower_right);
+        assert_eq!(pixels, expected);
+    }
+}
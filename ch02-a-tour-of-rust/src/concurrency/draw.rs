@@ -1,9 +1,12 @@
-use crate::concurrency::mandelbrot::escape_time;
-use crate::concurrency::parse::pixel_to_point;
+use crate::concurrency::mandelbrot::{distance_estimate, escape_time};
+use crate::concurrency::parse::{pixel_to_point, point_to_pixel};
+use crate::concurrency::threads::pin_worker;
 use image::ColorType;
 use image::png::PNGEncoder;
 use num::Complex;
 use std::fs::File;
+use std::io::Write;
+use std::sync::Mutex;
 
 /// 把一个矩形区域内的曼德勃罗集渲染到像素的缓冲区里。
 ///
@@ -16,19 +19,340 @@ pub fn render(
     upper_left: Complex<f64>,
     lower_right: Complex<f64>,
 ) {
+    render_window(pixels, bounds, upper_left, lower_right, (0, 0), bounds);
+}
+
+/// 只渲染整幅图像中左上角为（`left`，`top`）、宽高为`size`的一块，写入`pixels`。
+///
+/// `bounds`、`upper_left`和`lower_right`描述的是整幅图像。每个像素的点按它在
+/// 整幅图像里的位置计算，所以无论怎样分块，拼起来都和对整幅图像调用`render`
+/// 的结果完全相同。
+pub fn render_window(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    (left, top): (usize, usize),
+    size: (usize, usize),
+) {
+    assert_eq!(pixels.len(), size.0 * size.1);
+    assert!(left + size.0 <= bounds.0 && top + size.1 <= bounds.1);
+
+    for row in 0..size.1 {
+        for column in 0..size.0 {
+            let pixel = (left + column, top + row);
+            let point = pixel_to_point(bounds, pixel, upper_left, lower_right);
+            pixels[row * size.0 + column] = escape_shade(point);
+        }
+    }
+}
+
+/// 和`render`相同，只渲染整幅图像中的一个条带，供`render_bands`使用。
+pub fn render_band(pixels: &mut [u8], band: Band) {
+    render_window(
+        pixels,
+        band.bounds,
+        band.upper_left,
+        band.lower_right,
+        (0, band.top),
+        band.size(),
+    );
+}
+
+/// `render`给一个点的灰度：集合内部是黑色，逃逸得越快越亮。
+fn escape_shade(point: Complex<f64>) -> u8 {
+    match escape_time(point, 255) {
+        None => 0,
+        Some(count) => 255 - count as u8,
+    }
+}
+
+/// 不再细分的矩形的最小边长，更小的矩形直接逐个像素计算。
+const SUBDIVIDE_MIN_SIZE: usize = 6;
+
+/// 用 Mariani–Silver 细分法渲染，参数和`render_band`相同。
+///
+/// 先计算矩形边框上的像素，如果它们的灰度都相同，就认为内部也相同，直接填充；
+/// 否则把矩形分成四块分别处理。集合内部和远离集合的大片区域都不用逐点计算，
+/// 缩小的视图因此快得多。
+///
+/// 这个方法假设边框相同的矩形内部没有别的细节。集合本身是连通的，但像素只是
+/// 采样，完全落在矩形内部、又没有被边框采到的细丝会被抹掉，所以结果和`render`
+/// 可能有少量像素不同（测试中不超过 0.5%）。
+pub fn render_subdivide(pixels: &mut [u8], band: Band) {
+    subdivide(pixels, band);
+}
+
+/// `render_subdivide`的实现，返回实际计算了的像素数。
+fn subdivide(pixels: &mut [u8], band: Band) -> usize {
+    let bounds = band.size();
     assert_eq!(pixels.len(), bounds.0 * bounds.1);
 
+    let mut computed = vec![false; pixels.len()];
+    let mut count = 0;
+    let mut shade = |pixels: &mut [u8], column: usize, row: usize| -> u8 {
+        let index = row * bounds.0 + column;
+        if !computed[index] {
+            pixels[index] = escape_shade(band.point(column, row));
+            computed[index] = true;
+            count += 1;
+        }
+        pixels[index]
+    };
+
+    // 待处理的矩形：左上角的列和行，以及宽和高
+    let mut rectangles = vec![(0, 0, bounds.0, bounds.1)];
+    while let Some((left, top, width, height)) = rectangles.pop() {
+        if width == 0 || height == 0 {
+            continue;
+        }
+        let right = left + width - 1;
+        let bottom = top + height - 1;
+        if width <= SUBDIVIDE_MIN_SIZE || height <= SUBDIVIDE_MIN_SIZE {
+            for row in top..=bottom {
+                for column in left..=right {
+                    shade(pixels, column, row);
+                }
+            }
+            continue;
+        }
+
+        let first = shade(pixels, left, top);
+        let mut uniform = true;
+        for column in left..=right {
+            uniform &= shade(pixels, column, top) == first;
+            uniform &= shade(pixels, column, bottom) == first;
+        }
+        for row in top..=bottom {
+            uniform &= shade(pixels, left, row) == first;
+            uniform &= shade(pixels, right, row) == first;
+        }
+
+        if uniform {
+            for row in top + 1..bottom {
+                pixels[row * bounds.0 + left + 1..row * bounds.0 + right].fill(first);
+            }
+        } else {
+            let half_width = width / 2;
+            let half_height = height / 2;
+            rectangles.push((left, top, half_width, half_height));
+            rectangles.push((left + half_width, top, width - half_width, half_height));
+            rectangles.push((left, top + half_height, half_width, height - half_height));
+            rectangles.push((
+                left + half_width,
+                top + half_height,
+                width - half_width,
+                height - half_height,
+            ));
+        }
+    }
+    count
+}
+
+/// 用距离估计法把曼德勃罗集的一个条带渲染到像素的缓冲区里。
+///
+/// 参数的含义和`render_band`相同。每个像素按它到集合边界的估计距离着色：
+/// 集合内部是黑色，离边界越近越暗，离边界一个像素以外的点接近白色。
+/// 因为距离是按像素大小归一化的，所以在任何缩放级别下都能得到清晰的线条。
+pub fn render_distance(pixels: &mut [u8], band: Band) {
+    let bounds = band.size();
+    assert_eq!(pixels.len(), bounds.0 * bounds.1);
+
+    let pixel_size = band.pixel_size().re;
     for row in 0..bounds.1 {
         for column in 0..bounds.0 {
-            let point = pixel_to_point(bounds, (column, row), upper_left, lower_right);
-            pixels[row * bounds.0 + column] = match escape_time(point, 255) {
+            let point = band.point(column, row);
+            pixels[row * bounds.0 + column] = match distance_estimate(point, 255) {
                 None => 0,
-                Some(count) => 255 - count as u8,
+                Some(distance) => {
+                    let t = (4.0 * distance / pixel_size).powf(0.2).min(1.0);
+                    (t * 255.0) as u8
+                }
             };
         }
     }
 }
 
+/// 整幅图像中的一个水平条带，`render_bands`把它交给渲染函数。
+///
+/// `bounds`、`upper_left`和`lower_right`描述的是整幅图像，`point`按像素在
+/// 整幅图像里的位置计算对应的点，所以无论怎样分条带，拼起来都和一次渲染
+/// 整幅图像的结果完全相同。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Band {
+    pub bounds: (usize, usize),
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>,
+    /// 条带的第一行。
+    pub top: usize,
+    pub rows: usize,
+    /// 渲染这个条带的线程的编号，从 0 开始。
+    pub worker: usize,
+}
+
+impl Band {
+    /// 覆盖整幅图像的条带，由第 0 个线程渲染。
+    pub fn whole(
+        bounds: (usize, usize),
+        upper_left: Complex<f64>,
+        lower_right: Complex<f64>,
+    ) -> Band {
+        Band {
+            bounds,
+            upper_left,
+            lower_right,
+            top: 0,
+            rows: bounds.1,
+            worker: 0,
+        }
+    }
+
+    /// 条带的宽和高，也就是渲染函数拿到的缓冲区的大小。
+    pub fn size(&self) -> (usize, usize) {
+        (self.bounds.0, self.rows)
+    }
+
+    /// 条带里第`row`行、第`column`列的像素对应的点。
+    pub fn point(&self, column: usize, row: usize) -> Complex<f64> {
+        pixel_to_point(
+            self.bounds,
+            (column, self.top + row),
+            self.upper_left,
+            self.lower_right,
+        )
+    }
+
+    /// 一个像素在复平面上的宽和高，向右、向下为正。
+    pub fn pixel_size(&self) -> Complex<f64> {
+        Complex {
+            re: (self.lower_right.re - self.upper_left.re) / self.bounds.0 as f64,
+            im: (self.upper_left.im - self.lower_right.im) / self.bounds.1 as f64,
+        }
+    }
+}
+
+/// `render_bands`把图像分成的条带数（行数不够时每行一个条带）。
+/// 条带比线程多，先做完的线程可以接着领下一个，各个线程的负载更均匀。
+pub const BANDS: usize = 64;
+
+/// 把`pixels`按行分成`BANDS`个水平条带，由`threads`个线程（不超过条带数）
+/// 轮流领取，每个条带用`render_band`渲染。
+///
+/// `render_band`的参数是条带的像素和描述条带位置的`Band`。`pixels`中每个像素
+/// 可以占多个元素（例如 RGB 占三个），每个像素的元素数由`pixels`的长度和
+/// `bounds`推算出来。调用过`threads::pin_bands`时，每个线程固定在一个核心上。
+pub fn render_bands<T, F>(
+    pixels: &mut [T],
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    threads: usize,
+    render_band: F,
+) where
+    T: Send,
+    F: Fn(&mut [T], Band) + Sync,
+{
+    if bounds.0 == 0 || bounds.1 == 0 {
+        return;
+    }
+    let channels = pixels.len() / (bounds.0 * bounds.1);
+    let rows_per_band = bounds.1.div_ceil(BANDS);
+    let bands: Vec<&mut [T]> = pixels
+        .chunks_mut(rows_per_band * bounds.0 * channels)
+        .collect();
+    let workers = threads.clamp(1, bands.len());
+    let bands = Mutex::new(bands.into_iter().enumerate());
+    let (bands, render_band) = (&bands, &render_band);
+    crossbeam::scope(|spawner| {
+        for worker in 0..workers {
+            spawner.spawn(move |_| {
+                pin_worker(worker);
+                loop {
+                    // 先取出条带再渲染，不要在渲染时持有锁
+                    let next = bands.lock().unwrap().next();
+                    let Some((i, pixels)) = next else {
+                        break;
+                    };
+                    let band = Band {
+                        bounds,
+                        upper_left,
+                        lower_right,
+                        top: rows_per_band * i,
+                        rows: pixels.len() / (bounds.0 * channels),
+                        worker,
+                    };
+                    render_band(pixels, band);
+                }
+            });
+        }
+    })
+    .expect("error joining threads");
+}
+
+/// 在 RGB 缓冲区`pixels`上用`color`画一条依次经过复平面上的点`points`的折线。
+///
+/// 其它参数的含义和`render`相同。每条线段先裁剪到图像覆盖的区域里，
+/// 再每隔半个像素取一个点，所以伸到很远处的线段也能画得连续。
+pub fn draw_polyline(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    points: &[Complex<f64>],
+    color: [u8; 3],
+) {
+    assert_eq!(pixels.len(), bounds.0 * bounds.1 * 3);
+
+    let pixel_size = (lower_right.re - upper_left.re).abs() / bounds.0 as f64;
+    for segment in points.windows(2) {
+        let Some((from, to)) = clip_segment(segment[0], segment[1], upper_left, lower_right) else {
+            continue;
+        };
+        let steps = ((to - from).norm() / pixel_size * 2.0).ceil().max(1.0) as usize;
+        for step in 0..=steps {
+            let point = from + (to - from) * (step as f64 / steps as f64);
+            if let Some((column, row)) = point_to_pixel(bounds, point, upper_left, lower_right) {
+                let offset = (row * bounds.0 + column) * 3;
+                pixels[offset..offset + 3].copy_from_slice(&color);
+            }
+        }
+    }
+}
+
+/// 把线段`from`-`to`裁剪到`upper_left`和`lower_right`围成的矩形里
+/// （Liang–Barsky 算法）。线段完全在矩形外面时返回`None`。
+fn clip_segment(
+    from: Complex<f64>,
+    to: Complex<f64>,
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+) -> Option<(Complex<f64>, Complex<f64>)> {
+    let delta = to - from;
+    let (mut low, mut high) = (0.0f64, 1.0f64);
+    // 每一项是（p，q），线段上参数为 t 的点在矩形里当且仅当 p * t <= q
+    let checks = [
+        (-delta.re, from.re - upper_left.re),
+        (delta.re, lower_right.re - from.re),
+        (-delta.im, from.im - lower_right.im),
+        (delta.im, upper_left.im - from.im),
+    ];
+    for (p, q) in checks {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            low = low.max(q / p);
+        } else {
+            high = high.min(q / p);
+        }
+    }
+    if low > high {
+        return None;
+    }
+    Some((from + delta * low, from + delta * high))
+}
+
 /// 把缓冲区`pixels`写入到文件`filename`，它的宽和高由`bounds`指定。
 pub fn write_image(
     filename: &str,
@@ -36,12 +360,187 @@ pub fn write_image(
     bounds: (usize, usize),
 ) -> Result<(), std::io::Error> {
     let output = File::create(filename)?;
+    encode_image(output, pixels, bounds)
+}
+
+/// 把灰度缓冲区`pixels`编码成 PNG 写入`output`，例如一个`Vec<u8>`或者网络连接。
+pub fn encode_image<W: Write>(
+    output: W,
+    pixels: &[u8],
+    bounds: (usize, usize),
+) -> Result<(), std::io::Error> {
     let encoder = PNGEncoder::new(output);
-    encoder.encode(
-        &pixels,
-        bounds.0 as u32,
-        bounds.1 as u32,
-        ColorType::Gray(8),
-    )?;
+    encoder.encode(pixels, bounds.0 as u32, bounds.1 as u32, ColorType::Gray(8))?;
     Ok(())
 }
+
+/// 把 RGB 缓冲区`pixels`写入到文件`filename`，每个像素占三个字节。
+pub fn write_image_rgb(
+    filename: &str,
+    pixels: &[u8],
+    bounds: (usize, usize),
+) -> Result<(), std::io::Error> {
+    let output = File::create(filename)?;
+    let encoder = PNGEncoder::new(output);
+    encoder.encode(pixels, bounds.0 as u32, bounds.1 as u32, ColorType::RGB(8))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_polyline() {
+        let bounds = (10, 10);
+        let upper_left = Complex { re: 0.0, im: 10.0 };
+        let lower_right = Complex { re: 10.0, im: 0.0 };
+        let mut pixels = vec![0; 10 * 10 * 3];
+        // 从很远的地方穿过图像的水平线，只有穿过的那一行被画上
+        let points = [Complex::new(-1e6, 4.5), Complex::new(1e6, 4.5)];
+        draw_polyline(
+            &mut pixels,
+            bounds,
+            upper_left,
+            lower_right,
+            &points,
+            [255, 0, 0],
+        );
+        for row in 0..10 {
+            for column in 0..10 {
+                let offset = (row * 10 + column) * 3;
+                let expected = if row == 5 { 255 } else { 0 };
+                assert_eq!(pixels[offset], expected, "{} {}", column, row);
+            }
+        }
+        // 完全在图像外面的线段
+        let outside = [Complex::new(-5.0, -5.0), Complex::new(-1.0, 20.0)];
+        let before = pixels.clone();
+        draw_polyline(
+            &mut pixels,
+            bounds,
+            upper_left,
+            lower_right,
+            &outside,
+            [0, 255, 0],
+        );
+        assert_eq!(pixels, before);
+    }
+
+    #[test]
+    fn test_render_window() {
+        // 实轴正好落在一行像素上，按条带重新计算角点的话这一行会有偏差
+        let bounds = (40, 30);
+        let upper_left = Complex { re: -2.0, im: 1.2 };
+        let lower_right = Complex { re: 1.0, im: -1.2 };
+        let mut expected = vec![0; bounds.0 * bounds.1];
+        render(&mut expected, bounds, upper_left, lower_right);
+
+        let mut pixels = vec![0; bounds.0 * bounds.1];
+        for (left, top, width, height) in [(0, 0, 40, 13), (0, 13, 17, 17), (17, 13, 23, 17)] {
+            let mut window = vec![0; width * height];
+            render_window(
+                &mut window,
+                bounds,
+                upper_left,
+                lower_right,
+                (left, top),
+                (width, height),
+            );
+            for (row, line) in window.chunks(width).enumerate() {
+                let start = (top + row) * bounds.0 + left;
+                pixels[start..start + width].copy_from_slice(line);
+            }
+        }
+        assert_eq!(pixels, expected);
+    }
+
+    #[test]
+    fn test_render_distance() {
+        let bounds = (40, 30);
+        let mut pixels = vec![0; bounds.0 * bounds.1];
+        render_distance(
+            &mut pixels,
+            Band::whole(
+                bounds,
+                Complex { re: -2.0, im: 1.2 },
+                Complex { re: 1.0, im: -1.2 },
+            ),
+        );
+        // 左上角离集合很远，应该是白色
+        assert_eq!(pixels[0], 255);
+        // 图像中心附近（-0.5, 0）在集合内部，应该是黑色
+        assert_eq!(pixels[15 * bounds.0 + 20], 0);
+    }
+
+    #[test]
+    fn test_render_subdivide() {
+        let views = [
+            (Complex { re: -2.5, im: 1.2 }, Complex { re: 1.0, im: -1.2 }),
+            (
+                Complex {
+                    re: -1.20,
+                    im: 0.35,
+                },
+                Complex { re: -1.0, im: 0.20 },
+            ),
+        ];
+        let bounds = (200, 150);
+        for (upper_left, lower_right) in views {
+            let mut expected = vec![0; bounds.0 * bounds.1];
+            render(&mut expected, bounds, upper_left, lower_right);
+            let mut pixels = vec![0; bounds.0 * bounds.1];
+            let count = subdivide(&mut pixels, Band::whole(bounds, upper_left, lower_right));
+
+            let differences = pixels.iter().zip(&expected).filter(|(a, e)| a != e).count();
+            assert!(
+                differences * 200 <= pixels.len(),
+                "{} of {} pixels differ",
+                differences,
+                pixels.len()
+            );
+            assert!(count < pixels.len(), "computed every pixel");
+        }
+    }
+
+    #[test]
+    fn test_subdivide_skips_uniform_regions() {
+        // 整个区域都在主心形内部：只需要计算边框
+        let bounds = (100, 80);
+        let mut pixels = vec![1; bounds.0 * bounds.1];
+        let count = subdivide(
+            &mut pixels,
+            Band::whole(
+                bounds,
+                Complex { re: -0.2, im: 0.2 },
+                Complex { re: 0.1, im: -0.2 },
+            ),
+        );
+        assert_eq!(count, 2 * (bounds.0 + bounds.1) - 4);
+        assert!(pixels.iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn test_render_bands() {
+        // `main.rs`用法示例中的视图，实轴正好落在一行像素上
+        let bounds = (1000, 750);
+        let upper_left = Complex { re: -2.0, im: 1.2 };
+        let lower_right = Complex { re: 1.0, im: -1.2 };
+        let mut expected = vec![0; bounds.0 * bounds.1];
+        render(&mut expected, bounds, upper_left, lower_right);
+
+        // 行数不能被条带数整除，或者线程比条带还多，结果都应该和单线程完全相同
+        for threads in [1, 3, 8, 100] {
+            let mut pixels = vec![0; bounds.0 * bounds.1];
+            render_bands(
+                &mut pixels,
+                bounds,
+                upper_left,
+                lower_right,
+                threads,
+                render_band,
+            );
+            assert_eq!(pixels, expected, "threads = {}", threads);
+        }
+    }
+}
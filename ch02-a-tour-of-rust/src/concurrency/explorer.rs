@@ -0,0 +1,257 @@
+use crate::concurrency::render_cache::RenderCache;
+use crate::concurrency::viewport::Viewport;
+use num::Complex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// 自动寻找有趣区域的搜索参数。
+#[derive(Clone, Copy, Debug)]
+pub struct Search {
+    /// 往下放大的层数。
+    pub levels: usize,
+    /// 每一层保留的最好的候选区域数。
+    pub beam: usize,
+    /// 每个区域的预览分成`grid`×`grid`块，每一块是下一层的一个候选，
+    /// 所以每一层放大`grid`倍。
+    pub grid: usize,
+    /// 低分辨率预览的尺寸，宽和高都应该能被`grid`整除。
+    pub preview: (usize, usize),
+    /// 预览的迭代上限。
+    pub limit: usize,
+    /// 随机数种子。候选区域的位置会随机抖动一点，种子相同时结果完全相同。
+    pub seed: u64,
+    /// 渲染预览用的线程数，不影响结果。
+    pub threads: usize,
+}
+
+impl Default for Search {
+    fn default() -> Search {
+        Search {
+            levels: 4,
+            beam: 4,
+            grid: 4,
+            preview: (64, 48),
+            limit: 500,
+            seed: 0,
+            threads: 1,
+        }
+    }
+}
+
+/// 搜索找到的一个区域。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Candidate {
+    pub viewport: Viewport,
+    /// `score_counts`给这个区域的预览打的分。
+    pub score: f64,
+    /// 在第几层找到的，从 1 开始。
+    pub level: usize,
+}
+
+/// 给预览中从（`left`，`top`）开始、宽高为`size`的一块打分，分数越高越有趣。
+///
+/// 分数是两部分之和：
+///
+/// - 边界密度：相邻（右边和下面）的两个像素一个在集合里、一个不在的比例；
+/// - 迭代次数的离散程度：逃逸的像素`ln(1 + count)`的标准差，除以`ln(1 + limit)`。
+///
+/// 全在集合内部或者全是平滑背景的块得分接近 0。
+pub fn score_counts(
+    counts: &[Option<usize>],
+    bounds: (usize, usize),
+    (left, top): (usize, usize),
+    size: (usize, usize),
+    limit: usize,
+) -> f64 {
+    let mut boundary = 0;
+    let mut escaped = Vec::new();
+    for row in top..top + size.1 {
+        for column in left..left + size.0 {
+            let count = counts[row * bounds.0 + column];
+            let inside = count.is_none();
+            if column + 1 < left + size.0 && counts[row * bounds.0 + column + 1].is_none() != inside
+            {
+                boundary += 1;
+            }
+            if row + 1 < top + size.1 && counts[(row + 1) * bounds.0 + column].is_none() != inside {
+                boundary += 1;
+            }
+            if let Some(count) = count {
+                escaped.push((1.0 + count as f64).ln());
+            }
+        }
+    }
+
+    let density = boundary as f64 / (size.0 * size.1) as f64;
+    if escaped.is_empty() {
+        return density;
+    }
+    let mean = escaped.iter().sum::<f64>() / escaped.len() as f64;
+    let variance =
+        escaped.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / escaped.len() as f64;
+    density + variance.sqrt() / (1.0 + limit as f64).ln()
+}
+
+/// 从`start`开始逐层放大，返回按分数从高到低排列的候选区域。
+///
+/// 每一层对当前保留的每个区域渲染一张低分辨率预览，把它分成`grid`×`grid`块
+/// 分别打分，所有块里分数最高的`beam`块（位置随机抖动不超过四分之一块）
+/// 成为下一层的区域。每个被渲染过的区域（`start`除外）都是一个候选。
+pub fn search(start: Viewport, search: &Search) -> Vec<Candidate> {
+    let mut rng = StdRng::seed_from_u64(search.seed);
+    let grid = search.grid.max(1);
+    let (width, height) = search.preview;
+    let tile = (width / grid, height / grid);
+
+    let mut candidates = Vec::new();
+    let mut frontier = vec![start];
+    for level in 0..=search.levels {
+        let mut proposals = Vec::new();
+        for &viewport in &frontier {
+            let cache =
+                RenderCache::with_threads(viewport, search.preview, search.limit, search.threads);
+            let counts = cache.counts();
+            if level > 0 {
+                candidates.push(Candidate {
+                    viewport,
+                    score: score_counts(
+                        counts,
+                        search.preview,
+                        (0, 0),
+                        search.preview,
+                        search.limit,
+                    ),
+                    level,
+                });
+            }
+            if level == search.levels {
+                continue;
+            }
+            for row in 0..grid {
+                for column in 0..grid {
+                    let corner = (column * tile.0, row * tile.1);
+                    let score = score_counts(counts, search.preview, corner, tile, search.limit);
+                    proposals.push((score, tile_viewport(viewport, grid, column, row)));
+                }
+            }
+        }
+
+        // `sort_by`是稳定排序，分数相同时保持原来的顺序，结果可以重现
+        proposals.sort_by(|a, b| b.0.total_cmp(&a.0));
+        frontier = proposals
+            .into_iter()
+            .take(search.beam)
+            .map(|(_, viewport)| {
+                let jitter = Complex {
+                    re: rng.gen_range(-0.25..=0.25) * viewport.width(),
+                    im: rng.gen_range(-0.25..=0.25) * viewport.height(),
+                };
+                Viewport::new(viewport.upper_left + jitter, viewport.lower_right + jitter)
+            })
+            .collect();
+    }
+
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    candidates
+}
+
+/// 把`viewport`分成`grid`×`grid`块，返回第`row`行第`column`列的一块。
+fn tile_viewport(viewport: Viewport, grid: usize, column: usize, row: usize) -> Viewport {
+    let size = Complex {
+        re: viewport.width() / grid as f64,
+        im: -viewport.height() / grid as f64,
+    };
+    let upper_left = viewport.upper_left
+        + Complex {
+            re: size.re * column as f64,
+            im: size.im * row as f64,
+        };
+    Viewport::new(upper_left, upper_left + size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full() -> Viewport {
+        Viewport::new(Complex { re: -2.5, im: 1.5 }, Complex { re: 1.5, im: -1.5 })
+    }
+
+    fn small() -> Search {
+        Search {
+            levels: 2,
+            beam: 2,
+            grid: 4,
+            preview: (32, 24),
+            limit: 200,
+            seed: 7,
+            threads: 1,
+        }
+    }
+
+    fn score(viewport: Viewport) -> f64 {
+        let bounds = (32, 24);
+        let cache = RenderCache::new(viewport, bounds, 200);
+        score_counts(cache.counts(), bounds, (0, 0), bounds, 200)
+    }
+
+    #[test]
+    fn test_score_counts() {
+        // 主心形内部
+        let inside = Viewport::new(Complex { re: -0.2, im: 0.2 }, Complex { re: 0.1, im: -0.2 });
+        assert_eq!(score(inside), 0.0);
+        // 离集合很远的平滑背景
+        let far = Viewport::new(Complex { re: 5.0, im: 5.0 }, Complex { re: 5.1, im: 4.9 });
+        // 海马谷
+        let seahorse = Viewport::new(
+            Complex {
+                re: -0.80,
+                im: 0.20,
+            },
+            Complex {
+                re: -0.70,
+                im: 0.10,
+            },
+        );
+        assert!(score(seahorse) > score(far));
+        assert!(score(seahorse) > score(inside));
+    }
+
+    #[test]
+    fn test_tile_viewport() {
+        let tile = tile_viewport(full(), 4, 1, 2);
+        assert_eq!(tile.upper_left, Complex { re: -1.5, im: 0.0 });
+        assert_eq!(
+            tile.lower_right,
+            Complex {
+                re: -0.5,
+                im: -0.75
+            }
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let candidates = search(full(), &small());
+        // 每层保留两个区域
+        assert_eq!(candidates.len(), 4);
+        assert!(candidates.windows(2).all(|w| w[0].score >= w[1].score));
+        assert!(candidates.iter().all(|c| c.score > 0.0));
+        assert!(candidates.iter().any(|c| c.level == 2));
+        // 第二层的区域是整个视图的 1/16
+        let deep = candidates.iter().find(|c| c.level == 2).unwrap();
+        assert!((deep.viewport.width() - full().width() / 16.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_search_is_reproducible() {
+        assert_eq!(search(full(), &small()), search(full(), &small()));
+        let other = Search { seed: 8, ..small() };
+        assert_ne!(search(full(), &small()), search(full(), &other));
+        let parallel = Search {
+            threads: 3,
+            ..small()
+        };
+        assert_eq!(search(full(), &small()), search(full(), &parallel));
+    }
+}
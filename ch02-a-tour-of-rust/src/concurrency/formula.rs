@@ -0,0 +1,656 @@
+use crate::concurrency::draw::Band;
+use num::Complex;
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+/// `Formula::escape_time`使用的逃逸半径的平方，和`escape_time`相同。
+const FORMULA_ESCAPE_RADIUS_SQR: f64 = 4.0;
+
+/// 一个用户给出的迭代公式，例如`z^3 + c*sin(z)`或者`conj(z)^2 + c`。
+///
+/// 公式里可以使用变量`z`（当前的值）、`c`（像素对应的点）和`n`（已经迭代的次数），
+/// 常量`i`和`pi`，四则运算、`^`乘方，以及`sin`、`cos`、`tan`、`sinh`、`cosh`、
+/// `tanh`、`exp`、`ln`、`sqrt`、`abs`、`conj`、`re`、`im`这些单参数函数。
+/// 数字后面紧跟变量、函数或者括号时表示相乘，例如`2z`、`3sin(z)`。
+///
+/// 解析时公式被编译成栈式字节码，迭代时不用再解析文本。
+#[derive(Clone, Debug)]
+pub struct Formula {
+    source: String,
+    code: Vec<Op>,
+    stack_depth: usize,
+}
+
+/// 字节码指令。运算的操作数从栈顶取出，结果压回栈顶。
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Const(Complex<f64>),
+    Z,
+    C,
+    N,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Neg,
+    /// 整数次幂，比一般的复数次幂快得多，也更精确。
+    PowI(i32),
+    Pow,
+    Call(Function),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Exp,
+    Ln,
+    Sqrt,
+    Abs,
+    Conj,
+    Re,
+    Im,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        Some(match name {
+            "sin" => Function::Sin,
+            "cos" => Function::Cos,
+            "tan" => Function::Tan,
+            "sinh" => Function::Sinh,
+            "cosh" => Function::Cosh,
+            "tanh" => Function::Tanh,
+            "exp" => Function::Exp,
+            "ln" | "log" => Function::Ln,
+            "sqrt" => Function::Sqrt,
+            "abs" => Function::Abs,
+            "conj" => Function::Conj,
+            "re" => Function::Re,
+            "im" => Function::Im,
+            _ => return None,
+        })
+    }
+
+    fn apply(self, x: Complex<f64>) -> Complex<f64> {
+        match self {
+            Function::Sin => x.sin(),
+            Function::Cos => x.cos(),
+            Function::Tan => x.tan(),
+            Function::Sinh => x.sinh(),
+            Function::Cosh => x.cosh(),
+            Function::Tanh => x.tanh(),
+            Function::Exp => x.exp(),
+            Function::Ln => x.ln(),
+            Function::Sqrt => x.sqrt(),
+            Function::Abs => Complex::new(x.norm(), 0.0),
+            Function::Conj => x.conj(),
+            Function::Re => Complex::new(x.re, 0.0),
+            Function::Im => Complex::new(x.im, 0.0),
+        }
+    }
+}
+
+impl Formula {
+    /// 用给定的`z`、`c`和`n`求公式的值。
+    pub fn eval(&self, z: Complex<f64>, c: Complex<f64>, n: usize) -> Complex<f64> {
+        let mut stack = Vec::with_capacity(self.stack_depth);
+        self.run(&mut stack, z, c, n)
+    }
+
+    /// 和`mandelbrot::escape_time`相同，但用`z = f(z, c, n)`代替`z = z * z + c`，
+    /// 其中`n`是已经迭代的次数。`z`从 0 开始；变成 NaN 或者无穷大的点也算逃逸。
+    pub fn escape_time(&self, c: Complex<f64>, limit: usize) -> Option<usize> {
+        let mut stack = Vec::with_capacity(self.stack_depth);
+        let mut z = Complex { re: 0.0, im: 0.0 };
+        for i in 0..limit {
+            if z.norm_sqr() > FORMULA_ESCAPE_RADIUS_SQR || z.is_nan() {
+                return Some(i);
+            }
+            z = self.run(&mut stack, z, c, i);
+        }
+        None
+    }
+
+    /// 执行字节码。`stack`由调用者提供，这样迭代时不用每次都分配内存。
+    fn run(
+        &self,
+        stack: &mut Vec<Complex<f64>>,
+        z: Complex<f64>,
+        c: Complex<f64>,
+        n: usize,
+    ) -> Complex<f64> {
+        stack.clear();
+        for op in &self.code {
+            let value = match *op {
+                Op::Const(value) => value,
+                Op::Z => z,
+                Op::C => c,
+                Op::N => Complex::new(n as f64, 0.0),
+                Op::Neg => -stack.pop().unwrap(),
+                Op::PowI(exponent) => stack.pop().unwrap().powi(exponent),
+                Op::Call(function) => function.apply(stack.pop().unwrap()),
+                Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow => {
+                    let right = stack.pop().unwrap();
+                    let left = stack.pop().unwrap();
+                    match *op {
+                        Op::Add => left + right,
+                        Op::Sub => left - right,
+                        Op::Mul => left * right,
+                        Op::Div => left / right,
+                        _ => left.powc(right),
+                    }
+                }
+            };
+            stack.push(value);
+        }
+        stack.pop().expect("compiled formula leaves one value")
+    }
+}
+
+/// 只比较公式的文本。
+impl PartialEq for Formula {
+    fn eq(&self, other: &Formula) -> bool {
+        self.source == other.source
+    }
+}
+
+impl FromStr for Formula {
+    type Err = ParseFormulaError;
+
+    fn from_str(s: &str) -> Result<Formula, ParseFormulaError> {
+        FormulaParser::new(s).parse()
+    }
+}
+
+/// 输出公式原来的文本（去掉首尾空白），`from_str`可以再解析它。
+impl fmt::Display for Formula {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// 解析公式出错的原因。
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormulaErrorKind {
+    /// 输入为空，或者只有空白。
+    Empty,
+    /// 出现了不应该出现的字符。
+    UnexpectedChar(char),
+    /// 输入在应该还有内容的地方结束了。
+    UnexpectedEnd,
+    /// 不认识的变量名。
+    UnknownVariable(String),
+    /// 不认识的函数名。
+    UnknownFunction(String),
+}
+
+/// 解析公式返回的错误。`position`是出错的字符（或者名字的第一个字符）的位置，
+/// 按字符从 0 开始计数。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseFormulaError {
+    pub position: usize,
+    pub kind: FormulaErrorKind,
+}
+
+impl ParseFormulaError {
+    /// 返回两行文本：原始输入，以及下一行指向出错字符的`^`。
+    pub fn pointer(&self, input: &str) -> String {
+        format!("{}\n{}^", input, " ".repeat(self.position))
+    }
+}
+
+impl fmt::Display for ParseFormulaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            FormulaErrorKind::Empty => write!(f, "empty formula"),
+            FormulaErrorKind::UnexpectedChar(c) => {
+                write!(
+                    f,
+                    "unexpected character '{}' at position {}",
+                    c, self.position
+                )
+            }
+            FormulaErrorKind::UnexpectedEnd => {
+                write!(f, "unexpected end of input at position {}", self.position)
+            }
+            FormulaErrorKind::UnknownVariable(name) => write!(
+                f,
+                "unknown variable {:?} at position {} (expected z, c, n, i or pi)",
+                name, self.position
+            ),
+            FormulaErrorKind::UnknownFunction(name) => {
+                write!(
+                    f,
+                    "unknown function {:?} at position {}",
+                    name, self.position
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseFormulaError {}
+
+/// 把公式编译成字节码的递归下降解析器。优先级从低到高依次是：
+/// `+ -`、`* /`、一元的`-`、`^`（右结合）、数字、变量、函数调用和括号。
+struct FormulaParser {
+    chars: Vec<char>,
+    pos: usize,
+    code: Vec<Op>,
+    depth: usize,
+    stack_depth: usize,
+}
+
+impl FormulaParser {
+    fn new(s: &str) -> FormulaParser {
+        FormulaParser {
+            chars: s.chars().collect(),
+            pos: 0,
+            code: Vec::new(),
+            depth: 0,
+            stack_depth: 0,
+        }
+    }
+
+    fn parse(mut self) -> Result<Formula, ParseFormulaError> {
+        self.skip_whitespace();
+        if self.peek().is_none() {
+            return Err(self.error(FormulaErrorKind::Empty));
+        }
+        self.expression()?;
+        self.skip_whitespace();
+        if self.peek().is_some() {
+            return Err(self.unexpected());
+        }
+        let source: String = self.chars.iter().collect();
+        Ok(Formula {
+            source: source.trim().to_string(),
+            code: self.code,
+            stack_depth: self.stack_depth,
+        })
+    }
+
+    fn expression(&mut self) -> Result<(), ParseFormulaError> {
+        self.term()?;
+        loop {
+            self.skip_whitespace();
+            let op = match self.peek() {
+                Some('+') => Op::Add,
+                Some('-' | '\u{2212}') => Op::Sub,
+                _ => return Ok(()),
+            };
+            self.pos += 1;
+            self.term()?;
+            self.emit(op);
+        }
+    }
+
+    fn term(&mut self) -> Result<(), ParseFormulaError> {
+        self.unary()?;
+        loop {
+            self.skip_whitespace();
+            let op = match self.peek() {
+                Some('*') => Op::Mul,
+                Some('/') => Op::Div,
+                _ => return Ok(()),
+            };
+            self.pos += 1;
+            self.unary()?;
+            self.emit(op);
+        }
+    }
+
+    fn unary(&mut self) -> Result<(), ParseFormulaError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('+') => {
+                self.pos += 1;
+                self.unary()
+            }
+            Some('-' | '\u{2212}') => {
+                self.pos += 1;
+                self.unary()?;
+                // 负常数直接折叠，`z^-2`这样的指数仍然能用整数次幂
+                match self.code.last_mut() {
+                    Some(Op::Const(value)) => *value = -*value,
+                    _ => self.emit(Op::Neg),
+                }
+                Ok(())
+            }
+            _ => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<(), ParseFormulaError> {
+        self.primary()?;
+        self.skip_whitespace();
+        if !self.eat('^') {
+            return Ok(());
+        }
+        let exponent_start = self.code.len();
+        self.unary()?;
+        match self.code[exponent_start..] {
+            [Op::Const(e)] if e.im == 0.0 && e.re.fract() == 0.0 && e.re.abs() <= 1024.0 => {
+                self.code.pop();
+                self.depth -= 1;
+                self.emit(Op::PowI(e.re as i32));
+            }
+            _ => self.emit(Op::Pow),
+        }
+        Ok(())
+    }
+
+    fn primary(&mut self) -> Result<(), ParseFormulaError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                self.expression()?;
+                self.expect(')')
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let value = self.number()?;
+                self.emit(Op::Const(value));
+                // `2z`、`3sin(z)`、`2(z+1)`：数字后面紧跟着的因子和它相乘
+                if self.peek().is_some_and(|c| c.is_alphabetic() || c == '(') {
+                    self.power()?;
+                    self.emit(Op::Mul);
+                }
+                Ok(())
+            }
+            Some(c) if c.is_alphabetic() => {
+                let start = self.pos;
+                let name = self.identifier();
+                self.skip_whitespace();
+                if self.peek() == Some('(') {
+                    let function = Function::from_name(&name).ok_or(ParseFormulaError {
+                        position: start,
+                        kind: FormulaErrorKind::UnknownFunction(name),
+                    })?;
+                    self.pos += 1;
+                    self.expression()?;
+                    self.expect(')')?;
+                    self.emit(Op::Call(function));
+                    return Ok(());
+                }
+                let op = match name.as_str() {
+                    "z" => Op::Z,
+                    "c" => Op::C,
+                    "n" => Op::N,
+                    "i" | "j" => Op::Const(Complex::new(0.0, 1.0)),
+                    "pi" => Op::Const(Complex::new(PI, 0.0)),
+                    _ => {
+                        return Err(ParseFormulaError {
+                            position: start,
+                            kind: FormulaErrorKind::UnknownVariable(name),
+                        });
+                    }
+                };
+                self.emit(op);
+                Ok(())
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// 不带符号的十进制数，例如`12`、`.5`、`2.5e-4`，后面紧跟`i`时是纯虚数。
+    fn number(&mut self) -> Result<Complex<f64>, ParseFormulaError> {
+        let start = self.pos;
+        let mut text = String::new();
+        self.digits(&mut text);
+        if self.eat('.') {
+            text.push('.');
+            self.digits(&mut text);
+        }
+        if !text.chars().any(|c| c.is_ascii_digit()) {
+            self.pos = start;
+            return Err(self.unexpected());
+        }
+        // 只有后面真的跟着指数时才把`e`当作指数的开始，`2exp(z)`里的`e`不是
+        if matches!(self.peek(), Some('e' | 'E')) {
+            let sign = match self.chars.get(self.pos + 1) {
+                Some('+') => Some('+'),
+                Some('-' | '\u{2212}') => Some('-'),
+                _ => None,
+            };
+            let digits_at = self.pos + 1 + sign.is_some() as usize;
+            if self
+                .chars
+                .get(digits_at)
+                .is_some_and(|c| c.is_ascii_digit())
+            {
+                text.push('e');
+                text.extend(sign);
+                self.pos = digits_at;
+                self.digits(&mut text);
+            }
+        }
+        let value = f64::from_str(&text).expect("number text is always valid");
+
+        let imaginary = matches!(self.peek(), Some('i' | 'j'))
+            && !self
+                .chars
+                .get(self.pos + 1)
+                .is_some_and(|c| c.is_alphanumeric());
+        if imaginary {
+            self.pos += 1;
+            return Ok(Complex::new(0.0, value));
+        }
+        Ok(Complex::new(value, 0.0))
+    }
+
+    fn digits(&mut self, text: &mut String) {
+        while let Some(c) = self.peek().filter(char::is_ascii_digit) {
+            text.push(c);
+            self.pos += 1;
+        }
+    }
+
+    fn identifier(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_alphanumeric() || *c == '_') {
+            name.push(c);
+            self.pos += 1;
+        }
+        name
+    }
+
+    /// 追加一条指令，同时记录执行时栈的最大深度。
+    fn emit(&mut self, op: Op) {
+        match op {
+            Op::Const(_) | Op::Z | Op::C | Op::N => self.depth += 1,
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow => self.depth -= 1,
+            Op::Neg | Op::PowI(_) | Op::Call(_) => {}
+        }
+        self.stack_depth = self.stack_depth.max(self.depth);
+        self.code.push(op);
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseFormulaError> {
+        self.skip_whitespace();
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn unexpected(&self) -> ParseFormulaError {
+        match self.peek() {
+            Some(c) => self.error(FormulaErrorKind::UnexpectedChar(c)),
+            None => self.error(FormulaErrorKind::UnexpectedEnd),
+        }
+    }
+
+    fn error(&self, kind: FormulaErrorKind) -> ParseFormulaError {
+        ParseFormulaError {
+            position: self.pos,
+            kind,
+        }
+    }
+}
+
+/// 用迭代公式`formula`代替`z * z + c`，把条带`band`渲染到灰度缓冲区`pixels`里。
+///
+/// 其它参数的含义和`render_band`相同，着色方式也相同。
+pub fn render_formula(pixels: &mut [u8], band: Band, formula: &Formula) {
+    let bounds = band.size();
+    assert_eq!(pixels.len(), bounds.0 * bounds.1);
+
+    for row in 0..bounds.1 {
+        for column in 0..bounds.0 {
+            let point = band.point(column, row);
+            pixels[row * bounds.0 + column] = match formula.escape_time(point, 255) {
+                None => 0,
+                Some(count) => 255 - count as u8,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrency::draw::render;
+
+    fn c(re: f64, im: f64) -> Complex<f64> {
+        Complex { re, im }
+    }
+
+    fn formula(s: &str) -> Formula {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_eval() {
+        let z = c(1.0, 2.0);
+        let p = c(0.5, -0.25);
+        assert_eq!(formula("z^2 + c").eval(z, p, 0), z * z + p);
+        assert_eq!(
+            formula("conj(z)^2 + c").eval(z, p, 0),
+            z.conj() * z.conj() + p
+        );
+        assert_eq!(
+            formula("z^3 + c*sin(z)").eval(z, p, 0),
+            z.powi(3) + p * z.sin()
+        );
+        assert_eq!(formula("2z - 3i").eval(z, p, 0), c(2.0, 1.0));
+        assert_eq!(formula("-z^2").eval(z, p, 0), -(z * z));
+        assert_eq!(formula("z^-1").eval(z, p, 0), z.powi(-1));
+        // `^`是右结合的：2^(3^2)，指数不是常数，所以用的是一般的复数次幂
+        assert!((formula("2^3^2").eval(z, p, 0) - c(512.0, 0.0)).norm() < 1e-9);
+        assert_eq!(formula("n / 2 - 1").eval(z, p, 7), c(2.5, 0.0));
+        assert_eq!(formula("1e-3 + re(z) * im(z)").eval(z, p, 0), c(2.001, 0.0));
+        assert_eq!(formula("abs(z - 1)").eval(c(4.0, 4.0), p, 0), c(5.0, 0.0));
+        assert_eq!(formula("2exp(0)").eval(z, p, 0), c(2.0, 0.0));
+    }
+
+    #[test]
+    fn test_compile() {
+        // 整数次幂编译成`PowI`，负号折叠进常数
+        assert_eq!(formula("z^2").code, vec![Op::Z, Op::PowI(2)]);
+        assert_eq!(formula("z^-3").code, vec![Op::Z, Op::PowI(-3)]);
+        assert_eq!(formula("z^1.5").code.last(), Some(&Op::Pow));
+        assert_eq!(formula("z").stack_depth, 1);
+        assert_eq!(formula("z * z + c").stack_depth, 2);
+        assert_eq!(formula("z + (z + (z + c))").stack_depth, 4);
+    }
+
+    #[test]
+    fn test_parse_formula_errors() {
+        let error = |s: &str| s.parse::<Formula>().unwrap_err();
+        assert_eq!(error("  ").kind, FormulaErrorKind::Empty);
+        assert_eq!(
+            error("z^2 + x"),
+            ParseFormulaError {
+                position: 6,
+                kind: FormulaErrorKind::UnknownVariable("x".to_string())
+            }
+        );
+        assert_eq!(
+            error("z + cot(z)"),
+            ParseFormulaError {
+                position: 4,
+                kind: FormulaErrorKind::UnknownFunction("cot".to_string())
+            }
+        );
+        assert_eq!(
+            error("z^2 +"),
+            ParseFormulaError {
+                position: 5,
+                kind: FormulaErrorKind::UnexpectedEnd
+            }
+        );
+        assert_eq!(
+            error("(z + c"),
+            ParseFormulaError {
+                position: 6,
+                kind: FormulaErrorKind::UnexpectedEnd
+            }
+        );
+        assert_eq!(
+            error("z z"),
+            ParseFormulaError {
+                position: 2,
+                kind: FormulaErrorKind::UnexpectedChar('z')
+            }
+        );
+        assert_eq!(error("z^2 + x").pointer("z^2 + x"), "z^2 + x\n      ^");
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        let f = formula("  z^3 + c*sin(z) ");
+        assert_eq!(f.to_string(), "z^3 + c*sin(z)");
+        assert_eq!(f.to_string().parse(), Ok(f));
+    }
+
+    #[test]
+    fn test_escape_time_matches_mandelbrot() {
+        // `z^2 + c`应该和内置的渲染逐像素相同
+        let bounds = (40, 30);
+        let upper_left = c(-2.0, 1.2);
+        let lower_right = c(1.0, -1.2);
+        let mut expected = vec![0; bounds.0 * bounds.1];
+        render(&mut expected, bounds, upper_left, lower_right);
+        let mut actual = vec![0; bounds.0 * bounds.1];
+        render_formula(
+            &mut actual,
+            Band::whole(bounds, upper_left, lower_right),
+            &formula("z^2 + c"),
+        );
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_escape_time_nan_escapes() {
+        // 0 / 0 是 NaN，应该算作逃逸而不是一直留在集合里
+        assert_eq!(formula("z / z").escape_time(c(0.0, 0.0), 10), Some(1));
+        assert_eq!(formula("z^2").escape_time(c(0.0, 0.0), 10), None);
+    }
+}
@@ -0,0 +1,378 @@
+use crate::concurrency::draw::render_bands;
+use crate::concurrency::mandelbrot::{escape_time, smooth_escape_time};
+use crate::concurrency::parse::pixel_to_point;
+use image::ColorType;
+use image::png::PNGEncoder;
+use num::Complex;
+use std::fs::File;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// 底座的厚度占`scale`的比例。
+const BASE_FRACTION: f64 = 0.05;
+
+/// 用哪个量做高度。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    /// `escape_time`的整数结果，得到一级一级的台阶。
+    EscapeTime,
+    /// `smooth_escape_time`的结果，得到平滑的斜坡。
+    Smooth,
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Field, String> {
+        match s {
+            "escape" => Ok(Field::EscapeTime),
+            "smooth" => Ok(Field::Smooth),
+            _ => Err(format!(
+                "unknown field {:?}, expected `escape` or `smooth`",
+                s
+            )),
+        }
+    }
+}
+
+/// 用`threads`个线程计算`render`的像素网格上每个像素的迭代次数（按行排列），
+/// 最多迭代`limit`次。集合里的点的值是`limit`。
+pub fn iteration_field(
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    limit: usize,
+    field: Field,
+    threads: usize,
+) -> Vec<f64> {
+    let mut values = vec![0.0; bounds.0 * bounds.1];
+    render_bands(
+        &mut values,
+        bounds,
+        upper_left,
+        lower_right,
+        threads,
+        |pixels, band| {
+            for row in 0..band.rows {
+                for column in 0..bounds.0 {
+                    let point = band.point(column, row);
+                    let value = match field {
+                        Field::EscapeTime => escape_time(point, limit).map(|n| n as f64),
+                        Field::Smooth => smooth_escape_time(point, limit),
+                    };
+                    pixels[row * bounds.0 + column] = value.unwrap_or(limit as f64);
+                }
+            }
+        },
+    );
+    values
+}
+
+/// 和`render`使用同一个像素网格的高度场，每个像素一个 0 到 1 之间的高度。
+///
+/// 迭代次数`n`映射成`ln(1 + n) / ln(1 + limit)`：迭代次数大多很小，
+/// 取对数之后靠近边界的细节才不会被压成一条细缝。集合里的点高度为 1，
+/// 是一块平台。
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeightField {
+    pub bounds: (usize, usize),
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>,
+    /// 按行排列的高度。
+    pub heights: Vec<f64>,
+}
+
+impl HeightField {
+    /// 用`threads`个线程计算高度场，最多迭代`limit`次。
+    pub fn compute(
+        bounds: (usize, usize),
+        upper_left: Complex<f64>,
+        lower_right: Complex<f64>,
+        limit: usize,
+        field: Field,
+        threads: usize,
+    ) -> HeightField {
+        let top = (1.0 + limit as f64).ln();
+        let heights = iteration_field(bounds, upper_left, lower_right, limit, field, threads)
+            .into_iter()
+            // 平滑值在离集合很远的地方可能略小于 0
+            .map(|value| ((1.0 + value.max(0.0)).ln() / top).min(1.0))
+            .collect();
+        HeightField {
+            bounds,
+            upper_left,
+            lower_right,
+            heights,
+        }
+    }
+
+    /// 把高度量化成 16 位整数，0 到 1 映射到 0 到 65535。
+    pub fn heightmap16(&self) -> Vec<u16> {
+        self.heights
+            .iter()
+            .map(|h| (h.clamp(0.0, 1.0) * u16::MAX as f64).round() as u16)
+            .collect()
+    }
+
+    /// 把 16 位高度图写成灰度 PNG。
+    pub fn write_png16(&self, filename: &str) -> io::Result<()> {
+        // PNG 的 16 位像素是大端字节序
+        let bytes: Vec<u8> = self
+            .heightmap16()
+            .iter()
+            .flat_map(|h| h.to_be_bytes())
+            .collect();
+        let encoder = PNGEncoder::new(File::create(filename)?);
+        encoder.encode(
+            &bytes,
+            self.bounds.0 as u32,
+            self.bounds.1 as u32,
+            ColorType::Gray(16),
+        )
+    }
+
+    /// 生成可以打印的封闭网格，见`Mesh`。
+    ///
+    /// 顶点的`x`、`y`是`pixel_to_point`给出的复平面坐标，`z`是高度乘以`scale`。
+    /// `step`大于 1 时每隔`step`个像素取一个顶点（最后一行和最后一列总是保留），
+    /// 顶点数大约减少到`1 / step²`。
+    pub fn mesh(&self, scale: f64, step: usize) -> Mesh {
+        let step = step.max(1);
+        let samples = |size: usize| {
+            let mut indices: Vec<usize> = (0..size).step_by(step).collect();
+            if indices.last() != Some(&(size - 1)) {
+                indices.push(size - 1);
+            }
+            indices
+        };
+        let columns = samples(self.bounds.0);
+        let rows = samples(self.bounds.1);
+        let (width, height) = (columns.len(), rows.len());
+        let mut mesh = Mesh::default();
+
+        // 表面
+        for &row in &rows {
+            for &column in &columns {
+                let point = pixel_to_point(
+                    self.bounds,
+                    (column, row),
+                    self.upper_left,
+                    self.lower_right,
+                );
+                let h = self.heights[row * self.bounds.0 + column];
+                mesh.vertices.push([point.re, point.im, h * scale]);
+            }
+        }
+        let surface = |column: usize, row: usize| row * width + column;
+        for row in 0..height.saturating_sub(1) {
+            for column in 0..width.saturating_sub(1) {
+                // 从上往下看是逆时针，法向量朝上
+                let a = surface(column, row);
+                let b = surface(column + 1, row);
+                let d = surface(column, row + 1);
+                let e = surface(column + 1, row + 1);
+                mesh.triangles.push([a, d, e]);
+                mesh.triangles.push([a, e, b]);
+            }
+        }
+        if width < 2 || height < 2 {
+            return mesh;
+        }
+
+        // 表面的边界，从上往下看按逆时针排列：左边向下，底边向右，右边向上，顶边向左
+        let mut ring = Vec::new();
+        ring.extend((0..height - 1).map(|row| surface(0, row)));
+        ring.extend((0..width - 1).map(|column| surface(column, height - 1)));
+        ring.extend((1..height).rev().map(|row| surface(width - 1, row)));
+        ring.extend((1..width).rev().map(|column| surface(column, 0)));
+
+        // 底座：边界正下方的一圈顶点，再加一个中心点把底面分成扇形
+        let floor = -BASE_FRACTION * scale;
+        let first_floor = mesh.vertices.len();
+        for &index in &ring {
+            let [x, y, _] = mesh.vertices[index];
+            mesh.vertices.push([x, y, floor]);
+        }
+        let center = mesh.vertices.len();
+        let [left, top, _] = mesh.vertices[surface(0, 0)];
+        let [right, bottom, _] = mesh.vertices[surface(width - 1, height - 1)];
+        mesh.vertices
+            .push([(left + right) / 2.0, (top + bottom) / 2.0, floor]);
+
+        for i in 0..ring.len() {
+            let j = (i + 1) % ring.len();
+            let (s_i, s_j) = (ring[i], ring[j]);
+            let (f_i, f_j) = (first_floor + i, first_floor + j);
+            // 侧壁的法向量朝外，底面的法向量朝下
+            mesh.triangles.push([s_i, f_i, f_j]);
+            mesh.triangles.push([s_i, f_j, s_j]);
+            mesh.triangles.push([center, f_j, f_i]);
+        }
+        mesh
+    }
+}
+
+/// 三角网格。`HeightField::mesh`生成的网格是封闭的：每条边正好属于两个
+/// 三角形，所有三角形的法向量（按右手定则）都朝外，可以直接交给切片软件。
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mesh {
+    pub vertices: Vec<[f64; 3]>,
+    /// 每个三角形的三个顶点在`vertices`里的下标。
+    pub triangles: Vec<[usize; 3]>,
+}
+
+impl Mesh {
+    /// 写成 Wavefront OBJ 文本格式。
+    pub fn write_obj<W: Write>(&self, mut output: W) -> io::Result<()> {
+        for [x, y, z] in &self.vertices {
+            writeln!(output, "v {} {} {}", x, y, z)?;
+        }
+        for [a, b, c] in &self.triangles {
+            // OBJ 的下标从 1 开始
+            writeln!(output, "f {} {} {}", a + 1, b + 1, c + 1)?;
+        }
+        Ok(())
+    }
+
+    /// 写成二进制 STL 格式。
+    pub fn write_stl<W: Write>(&self, mut output: W) -> io::Result<()> {
+        let mut header = [0; 80];
+        let title = b"mandelbrot heightmap";
+        header[..title.len()].copy_from_slice(title);
+        output.write_all(&header)?;
+        output.write_all(&(self.triangles.len() as u32).to_le_bytes())?;
+        for triangle in &self.triangles {
+            let [a, b, c] = triangle.map(|i| self.vertices[i]);
+            let normal = normalize(cross(sub(b, a), sub(c, a)));
+            for value in normal.iter().chain(&a).chain(&b).chain(&c) {
+                output.write_all(&(*value as f32).to_le_bytes())?;
+            }
+            // 属性字节数
+            output.write_all(&0u16.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(v: [f64; 3]) -> [f64; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if length == 0.0 {
+        return v;
+    }
+    [v[0] / length, v[1] / length, v[2] / length]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn field(bounds: (usize, usize), field: Field) -> HeightField {
+        HeightField::compute(
+            bounds,
+            Complex { re: -2.0, im: 1.2 },
+            Complex { re: 1.0, im: -1.2 },
+            100,
+            field,
+            3,
+        )
+    }
+
+    #[test]
+    fn test_height_field() {
+        let heights = field((30, 24), Field::EscapeTime);
+        assert!(heights.heights.iter().all(|h| (0.0..=1.0).contains(h)));
+        // 原点附近在集合里，是平台的最高处
+        let (column, row) = (20, 12);
+        assert_eq!(heights.heights[row * 30 + column], 1.0);
+        // 每个高度都对应一个整数迭代次数
+        assert!(heights.heights.iter().all(|h| {
+            let n = (h * 101f64.ln()).exp() - 1.0;
+            (n - n.round()).abs() < 1e-9
+        }));
+
+        let smooth = field((30, 24), Field::Smooth);
+        assert_eq!(smooth.heights[row * 30 + column], 1.0);
+        assert_ne!(smooth, heights);
+    }
+
+    #[test]
+    fn test_heightmap16() {
+        let heights = HeightField {
+            bounds: (3, 1),
+            upper_left: Complex { re: 0.0, im: 0.0 },
+            lower_right: Complex { re: 1.0, im: -1.0 },
+            heights: vec![0.0, 0.5, 1.0],
+        };
+        assert_eq!(heights.heightmap16(), vec![0, 32768, 65535]);
+    }
+
+    #[test]
+    fn test_mesh_is_closed() {
+        let heights = field((23, 17), Field::Smooth);
+        for step in [1, 4, 100] {
+            let mesh = heights.mesh(0.5, step);
+            // 每条有向边只出现一次，并且它的反向边也出现一次：网格是封闭的，
+            // 相邻三角形的朝向一致
+            let mut edges = HashSet::new();
+            for &[a, b, c] in &mesh.triangles {
+                for edge in [(a, b), (b, c), (c, a)] {
+                    assert!(edges.insert(edge), "step {}: duplicate edge", step);
+                }
+            }
+            assert!(edges.iter().all(|&(a, b)| edges.contains(&(b, a))));
+            // 欧拉示性数：V - E + F = 2
+            let euler = mesh.vertices.len() as isize - (edges.len() / 2) as isize
+                + mesh.triangles.len() as isize;
+            assert_eq!(euler, 2);
+        }
+    }
+
+    #[test]
+    fn test_mesh_decimation() {
+        let heights = field((21, 11), Field::EscapeTime);
+        let full = heights.mesh(1.0, 1);
+        let decimated = heights.mesh(1.0, 5);
+        // 列 0, 5, 10, 15, 20，行 0, 5, 10，再加底座的一圈和中心点
+        assert_eq!(decimated.vertices.len(), 5 * 3 + 12 + 1);
+        assert!(decimated.triangles.len() < full.triangles.len() / 10);
+        // 角上的顶点和完整网格相同
+        assert_eq!(decimated.vertices[0], full.vertices[0]);
+        assert_eq!(decimated.vertices[14], full.vertices[21 * 11 - 1]);
+        let top = full.vertices.iter().map(|v| v[2]).fold(f64::MIN, f64::max);
+        assert_eq!(top, 1.0);
+    }
+
+    #[test]
+    fn test_write_obj_and_stl() {
+        let mesh = Mesh {
+            vertices: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+            triangles: vec![[0, 1, 2]],
+        };
+        let mut obj = Vec::new();
+        mesh.write_obj(&mut obj).unwrap();
+        assert_eq!(
+            String::from_utf8(obj).unwrap(),
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n"
+        );
+
+        let mut stl = Vec::new();
+        mesh.write_stl(&mut stl).unwrap();
+        assert_eq!(stl.len(), 80 + 4 + 50);
+        assert_eq!(&stl[80..84], &1u32.to_le_bytes());
+        // 法向量是 (0, 0, 1)
+        assert_eq!(&stl[92..96], &1.0f32.to_le_bytes());
+    }
+}
@@ -0,0 +1,225 @@
+use crate::concurrency::draw::Band;
+use crate::concurrency::palette::diverging;
+use std::fmt;
+use std::str::FromStr;
+
+/// 逻辑斯谛映射`x ← r x (1 - x)`每一步使用的参数`r`取`a`还是`b`，
+/// 按顺序循环使用，例如`AB`或者`AABAB`。
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sequence {
+    /// 为真的一步使用`b`。
+    steps: Vec<bool>,
+}
+
+impl Sequence {
+    /// 第`n`步的参数`r`。
+    pub fn rate(&self, n: usize, a: f64, b: f64) -> f64 {
+        if self.steps[n % self.steps.len()] {
+            b
+        } else {
+            a
+        }
+    }
+}
+
+/// 由`A`和`B`组成的非空字符串，不区分大小写。
+impl FromStr for Sequence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Sequence, String> {
+        let steps = s
+            .chars()
+            .map(|c| match c {
+                'A' | 'a' => Ok(false),
+                'B' | 'b' => Ok(true),
+                _ => Err(format!(
+                    "invalid character {:?} in sequence, expected `A` or `B`",
+                    c
+                )),
+            })
+            .collect::<Result<Vec<bool>, String>>()?;
+        if steps.is_empty() {
+            return Err("empty sequence".to_string());
+        }
+        Ok(Sequence { steps })
+    }
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &step in &self.steps {
+            write!(f, "{}", if step { 'B' } else { 'A' })?;
+        }
+        Ok(())
+    }
+}
+
+/// 把李雅普诺夫指数映射成颜色的发散色方案。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scheme {
+    /// 稳定的区域从黑色过渡到金黄色，混沌的区域从黑色过渡到蓝色。
+    Classic,
+    /// 稳定的区域是蓝色，混沌的区域是红色，指数接近 0 的地方是浅灰色。
+    CoolWarm,
+}
+
+impl Scheme {
+    /// 指数`lambda`的颜色：先用`tanh`压缩到 -1 到 1 之间，负数（稳定）
+    /// 和正数（混沌）分别用一端的颜色。NaN 用中间的颜色。
+    pub fn color(&self, lambda: f64) -> [u8; 3] {
+        let t = lambda.tanh();
+        match self {
+            Scheme::Classic => diverging(t, [255, 210, 0], [0, 0, 0], [40, 90, 255]),
+            Scheme::CoolWarm => diverging(t, [59, 76, 192], [221, 221, 221], [180, 4, 38]),
+        }
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Scheme, String> {
+        match s {
+            "classic" => Ok(Scheme::Classic),
+            "coolwarm" => Ok(Scheme::CoolWarm),
+            _ => Err(format!(
+                "unknown palette {:?}, expected `classic` or `coolwarm`",
+                s
+            )),
+        }
+    }
+}
+
+/// 李雅普诺夫分形的参数。
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lyapunov {
+    pub sequence: Sequence,
+    /// 开始累计之前先迭代的次数，让轨迹离开初始值的影响。
+    pub warmup: usize,
+    /// 累计指数的迭代次数。
+    pub iterations: usize,
+    pub scheme: Scheme,
+}
+
+impl Lyapunov {
+    pub fn new(sequence: Sequence) -> Lyapunov {
+        Lyapunov {
+            sequence,
+            warmup: 200,
+            iterations: 800,
+            scheme: Scheme::Classic,
+        }
+    }
+
+    /// 参数为（`a`，`b`）时的李雅普诺夫指数
+    /// `λ = (1 / N) Σ ln|r_n (1 - 2 x_n)|`，从`x_0 = 0.5`开始迭代。
+    ///
+    /// 负数表示轨迹收敛到稳定的周期轨道，正数表示混沌。超稳定的点
+    /// （导数正好为 0）是负无穷，`r`大于 4 让轨迹发散时是正无穷。
+    pub fn exponent(&self, a: f64, b: f64) -> f64 {
+        let mut x = 0.5;
+        for n in 0..self.warmup {
+            let r = self.sequence.rate(n, a, b);
+            x = r * x * (1.0 - x);
+        }
+        let mut sum = 0.0;
+        for n in self.warmup..self.warmup + self.iterations {
+            let r = self.sequence.rate(n, a, b);
+            sum += (r * (1.0 - 2.0 * x)).abs().ln();
+            x = r * x * (1.0 - x);
+        }
+        sum / self.iterations as f64
+    }
+}
+
+/// 把条带`band`的李雅普诺夫分形渲染到 RGB 缓冲区`pixels`里。
+///
+/// 参数的含义和`render_band`相同，只是复平面上的点（`re`，`im`）被当作逻辑斯谛映射
+/// 的两个参数（`a`，`b`），通常取 0 到 4 之间的一块区域。
+pub fn render_lyapunov(pixels: &mut [u8], band: Band, lyapunov: &Lyapunov) {
+    let bounds = band.size();
+    assert_eq!(pixels.len(), bounds.0 * bounds.1 * 3);
+
+    for row in 0..bounds.1 {
+        for column in 0..bounds.0 {
+            let point = band.point(column, row);
+            let color = lyapunov.scheme.color(lyapunov.exponent(point.re, point.im));
+            let offset = (row * bounds.0 + column) * 3;
+            pixels[offset..offset + 3].copy_from_slice(&color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrency::draw::render_bands;
+    use num::Complex;
+
+    fn lyapunov(sequence: &str) -> Lyapunov {
+        Lyapunov::new(sequence.parse().unwrap())
+    }
+
+    #[test]
+    fn test_parse_sequence() {
+        let sequence: Sequence = "aabAB".parse().unwrap();
+        assert_eq!(sequence.to_string(), "AABAB");
+        assert_eq!(sequence.rate(2, 1.0, 2.0), 2.0);
+        assert_eq!(sequence.rate(5, 1.0, 2.0), 1.0);
+        assert!("".parse::<Sequence>().is_err());
+        assert_eq!(
+            "ABC".parse::<Sequence>(),
+            Err("invalid character 'C' in sequence, expected `A` or `B`".to_string())
+        );
+    }
+
+    #[test]
+    fn test_exponent() {
+        let ab = lyapunov("AB");
+        // 只用一个参数时就是普通的逻辑斯谛映射
+        assert!(ab.exponent(3.2, 3.2) < 0.0);
+        assert!(ab.exponent(3.9, 3.9) > 0.0);
+        // r = 2 时 x 一直是 0.5，导数为 0，是超稳定的
+        assert_eq!(ab.exponent(2.0, 2.0), f64::NEG_INFINITY);
+        // 轨迹发散到无穷大
+        assert_eq!(ab.exponent(4.5, 4.5), f64::INFINITY);
+        // 序列决定两个参数怎样交替，交换 a 和 b 的结果一般不同
+        let aab = lyapunov("AAB");
+        assert_ne!(aab.exponent(3.4, 3.8), aab.exponent(3.8, 3.4));
+    }
+
+    #[test]
+    fn test_scheme_color() {
+        assert_eq!(Scheme::Classic.color(0.0), [0, 0, 0]);
+        assert_eq!(Scheme::Classic.color(f64::NEG_INFINITY), [255, 210, 0]);
+        assert_eq!(Scheme::CoolWarm.color(f64::NAN), [221, 221, 221]);
+        assert_eq!("coolwarm".parse(), Ok(Scheme::CoolWarm));
+        assert!("rainbow".parse::<Scheme>().is_err());
+    }
+
+    #[test]
+    fn test_render_lyapunov() {
+        let bounds = (24, 16);
+        let upper_left = Complex { re: 2.0, im: 4.0 };
+        let lower_right = Complex { re: 4.0, im: 2.0 };
+        let mut params = lyapunov("AB");
+        params.iterations = 200;
+        let mut pixels = vec![0; bounds.0 * bounds.1 * 3];
+        render_bands(
+            &mut pixels,
+            bounds,
+            upper_left,
+            lower_right,
+            4,
+            |pixels, band| render_lyapunov(pixels, band, &params),
+        );
+        // 左下角（a、b 都接近 2）是稳定的，右上角（都接近 4）是混沌的
+        let pixel = |column: usize, row: usize| {
+            let offset = (row * bounds.0 + column) * 3;
+            [pixels[offset], pixels[offset + 1], pixels[offset + 2]]
+        };
+        let (stable, chaotic) = (pixel(0, bounds.1 - 1), pixel(bounds.0 - 1, 0));
+        assert!(stable[0] > stable[2], "{:?}", stable);
+        assert!(chaotic[2] > chaotic[0], "{:?}", chaotic);
+    }
+}
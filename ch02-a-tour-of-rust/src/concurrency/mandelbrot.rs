@@ -1,5 +1,11 @@
 use num::Complex;
 
+/// `distance_estimate`使用的逃逸半径的平方。
+const DE_ESCAPE_RADIUS_SQR: f64 = 1e6;
+
+/// 计算平滑迭代次数时继续迭代到的半径。半径越大，平滑值越准确。
+const SMOOTH_ESCAPE_RADIUS: f64 = 1000.0;
+
 /// 尝试判断`c`是否在曼德勃罗集里，最多迭代`limit`次。
 ///
 /// 如果`c`不在曼德勃罗集里，就返回`Some(i)`，其中`i`是
@@ -17,6 +23,63 @@ pub fn escape_time(c: Complex<f64>, limit: usize) -> Option<usize> {
     None
 }
 
+/// 和`escape_time`相同，但每次迭代之后都把迭代次数`i`和新的`z`传给`visit`。
+///
+/// `visit`依次看到`z_1`、`z_2`……，直到逃逸或者迭代了`limit`次，
+/// 逃逸时的那个`z`也会传给它。返回值和`escape_time`相同。
+pub fn escape_time_orbit<F>(c: Complex<f64>, limit: usize, mut visit: F) -> Option<usize>
+where
+    F: FnMut(usize, Complex<f64>),
+{
+    let mut z = Complex { re: 0.0, im: 0.0 };
+    for i in 0..limit {
+        if z.norm_sqr() > 4.0 {
+            return Some(i);
+        }
+        z = z * z + c;
+        visit(i + 1, z);
+    }
+    None
+}
+
+/// 平滑（连续）的迭代次数，最多迭代`limit`次。
+///
+/// 如果`c`在`limit`次之内逃逸了，就继续迭代到`|z_n|`超出`SMOOTH_ESCAPE_RADIUS`，
+/// 返回`Some(n + 1 - log2(ln|z_n|))`。它在逃逸时间相同的区域内连续变化，
+/// 适合做平滑的着色。没有逃逸时返回`None`。
+pub fn smooth_escape_time(c: Complex<f64>, limit: usize) -> Option<f64> {
+    escape_time(c, limit)?;
+    let mut z = Complex { re: 0.0, im: 0.0 };
+    let mut n = 0;
+    // 已经超出半径 2 了，再迭代几次就会超出更大的半径
+    while z.norm() <= SMOOTH_ESCAPE_RADIUS {
+        z = z * z + c;
+        n += 1;
+    }
+    Some(n as f64 + 1.0 - z.norm().ln().log2())
+}
+
+/// 估计`c`到曼德勃罗集边界的距离，最多迭代`limit`次。
+///
+/// 迭代`z`的同时跟踪它对`c`的导数`dz`：`dz' = 2 * z * dz + 1`。
+/// 如果`c`逃逸了，就返回`Some(d)`，其中`d = |z| * ln|z| / |dz|`
+/// 是`c`到边界距离的估计值。如果迭代了`limit`次之后仍未逃逸，
+/// 就返回`None`。为了让估计值更准确，这里用了比`escape_time`
+/// 大得多的逃逸半径。
+pub fn distance_estimate(c: Complex<f64>, limit: usize) -> Option<f64> {
+    let mut z = Complex { re: 0.0, im: 0.0 };
+    let mut dz = Complex { re: 0.0, im: 0.0 };
+    for _ in 0..limit {
+        if z.norm_sqr() > DE_ESCAPE_RADIUS_SQR {
+            let norm = z.norm();
+            return Some(norm * norm.ln() / dz.norm());
+        }
+        dz = 2.0 * z * dz + 1.0;
+        z = z * z + c;
+    }
+    None
+}
+
 pub fn complex_square_add_loop(c: Complex<f64>) -> Complex<f64> {
     let mut z = Complex { re: 0.0, im: 0.0 };
     for _ in 0..10 {
@@ -38,4 +101,43 @@ mod tests {
     fn test_escape_time() {
         println!("{:?}", escape_time(Complex { re: 1.0, im: 1.0 }, 10))
     }
+
+    #[test]
+    fn test_escape_time_orbit() {
+        for c in [
+            Complex { re: 1.0, im: 1.0 },
+            Complex { re: -0.75, im: 0.1 },
+            Complex { re: 0.0, im: 0.0 },
+        ] {
+            let mut orbit = Vec::new();
+            let count = escape_time_orbit(c, 50, |i, z| orbit.push((i, z)));
+            assert_eq!(count, escape_time(c, 50));
+            // 逃逸时看到 count 个点，没有逃逸时看到 limit 个点
+            assert_eq!(orbit.len(), count.unwrap_or(50));
+            assert_eq!(orbit.first(), Some(&(1, c)));
+        }
+    }
+
+    #[test]
+    fn test_smooth_escape_time() {
+        assert_eq!(smooth_escape_time(Complex { re: 0.0, im: 0.0 }, 100), None);
+        // 平滑值和逃逸时间相差不大
+        let c = Complex { re: 0.3, im: 0.0 };
+        let smooth = smooth_escape_time(c, 1000).unwrap();
+        let count = escape_time(c, 1000).unwrap() as f64;
+        assert!((smooth - count).abs() < 3.0, "{} {}", smooth, count);
+    }
+
+    #[test]
+    fn test_distance_estimate() {
+        // 原点在集合内部
+        assert_eq!(distance_estimate(Complex { re: 0.0, im: 0.0 }, 100), None);
+        // 实轴上集合最右端是 0.25，所以 c = 1 到边界的距离约为 0.75，
+        // 估计值和真实距离相差不超过 4 倍
+        let d = distance_estimate(Complex { re: 1.0, im: 0.0 }, 100).unwrap();
+        assert!(d > 0.75 / 4.0 && d < 0.75 * 4.0, "d = {}", d);
+        // 越靠近边界，距离越小
+        let near = distance_estimate(Complex { re: 0.3, im: 0.0 }, 100).unwrap();
+        assert!(near < d);
+    }
 }
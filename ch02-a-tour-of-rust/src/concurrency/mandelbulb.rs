@@ -0,0 +1,405 @@
+use crate::concurrency::draw::Band;
+use crate::concurrency::parse::{ParseTupleError, parse_tuple};
+use num::Complex;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
+
+/// 迭代时`|z|`超出它就认为逃逸了。
+const BAILOUT: f64 = 2.0;
+
+/// 光线最多前进的步数。
+const MAX_STEPS: usize = 256;
+
+/// 光线走出这个距离还没有碰到物体，就认为打到了背景。
+const MAX_DISTANCE: f64 = 20.0;
+
+/// 距离估计小于`HIT_EPSILON * t`时认为光线碰到了表面，`t`是已经走过的距离，
+/// 离相机越远要求越宽松，和像素的大小成正比。
+const HIT_EPSILON: f64 = 5e-4;
+
+/// 环境光遮蔽沿法向量采样的次数和间距。
+const AO_SAMPLES: usize = 5;
+const AO_STEP: f64 = 0.02;
+
+/// 三维向量。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vec3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Vec3 {
+    pub fn new(x: f64, y: f64, z: f64) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    pub fn dot(self, other: Vec3) -> f64 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn length(self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalize(self) -> Vec3 {
+        self * (1.0 / self.length())
+    }
+}
+
+impl Add for Vec3 {
+    type Output = Vec3;
+
+    fn add(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl Sub for Vec3 {
+    type Output = Vec3;
+
+    fn sub(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl Mul<f64> for Vec3 {
+    type Output = Vec3;
+
+    fn mul(self, k: f64) -> Vec3 {
+        Vec3::new(self.x * k, self.y * k, self.z * k)
+    }
+}
+
+impl Neg for Vec3 {
+    type Output = Vec3;
+
+    fn neg(self) -> Vec3 {
+        self * -1.0
+    }
+}
+
+/// 形如`0,0.5,-2.5`。
+impl FromStr for Vec3 {
+    type Err = ParseTupleError;
+
+    fn from_str(s: &str) -> Result<Vec3, ParseTupleError> {
+        let [x, y, z] = parse_tuple(s, ',', true)?;
+        Ok(Vec3 { x, y, z })
+    }
+}
+
+/// 相机：从`position`看向`target`，`fov`是竖直方向的视角（角度）。
+/// `y`轴朝上。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub position: Vec3,
+    pub target: Vec3,
+    pub fov: f64,
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera {
+            position: Vec3::new(0.0, 1.0, -3.2),
+            target: Vec3::new(0.0, 0.0, 0.0),
+            fov: 45.0,
+        }
+    }
+}
+
+impl Camera {
+    /// 检查相机能否确定光线的方向：`position`和`target`不能重合，
+    /// `fov`必须大于 0 度、小于 180 度。否则`ray`会得到 NaN 或者无穷大。
+    pub fn check(&self) -> Result<(), String> {
+        let distance = (self.target - self.position).length();
+        if !(distance > 0.0 && distance.is_finite()) {
+            return Err("camera position and target must be distinct finite points".to_string());
+        }
+        if !(self.fov > 0.0 && self.fov < 180.0) {
+            return Err(format!(
+                "field of view must be between 0 and 180 degrees, found {}",
+                self.fov
+            ));
+        }
+        Ok(())
+    }
+
+    /// 穿过屏幕坐标`screen`的光线的方向（单位向量）。屏幕坐标的虚部
+    /// 从底边的 -1 到顶边的 1，实部按宽高比伸缩，见`screen_corners`。
+    pub fn ray(&self, screen: Complex<f64>) -> Vec3 {
+        let forward = (self.target - self.position).normalize();
+        // 相机正对着上方或下方时，换一个参考方向
+        let reference = if forward.cross(Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9 {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            Vec3::new(0.0, 1.0, 0.0)
+        };
+        let right = reference.cross(forward).normalize();
+        let up = forward.cross(right);
+        let scale = (self.fov.to_radians() / 2.0).tan();
+        (forward + right * (screen.re * scale) + up * (screen.im * scale)).normalize()
+    }
+}
+
+/// 渲染`bounds`大小的图像时屏幕坐标的左上角和右下角，把它们当作`render_bands`
+/// 的复平面区域传进去，每个条带就能算出自己的像素对应的光线。
+pub fn screen_corners(bounds: (usize, usize)) -> (Complex<f64>, Complex<f64>) {
+    let aspect = bounds.0 as f64 / bounds.1 as f64;
+    (Complex::new(-aspect, 1.0), Complex::new(aspect, -1.0))
+}
+
+/// 曼德勃罗球的参数。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bulb {
+    /// 三元数（triplex）迭代`z ← z^power + c`的幂次，经典的曼德勃罗球是 8。
+    pub power: f64,
+    /// 最多迭代的次数。
+    pub iterations: usize,
+}
+
+impl Default for Bulb {
+    fn default() -> Bulb {
+        Bulb {
+            power: 8.0,
+            iterations: 12,
+        }
+    }
+}
+
+impl Bulb {
+    /// 估计点`p`到曼德勃罗球表面的距离，`p`在里面时返回 0。
+    ///
+    /// 三元数的幂在球坐标下计算：长度取`power`次方，两个角都乘以`power`。
+    /// 极轴是`y`轴，和相机的上方一致，这样默认看到的是侧面。
+    /// 同时跟踪导数的长度`dr' = power * r^(power - 1) * dr + 1`，
+    /// 距离估计是`0.5 * r * ln(r) / dr`，和二维的`distance_estimate`相同。
+    pub fn distance(&self, p: Vec3) -> f64 {
+        let mut z = p;
+        let mut dr = 1.0;
+        let mut r = z.length();
+        for _ in 0..self.iterations {
+            if r > BAILOUT {
+                break;
+            }
+            if r == 0.0 {
+                // 回到了原点，下一步又是`p`，轨迹是周期的，不会逃逸
+                return 0.0;
+            }
+            let theta = (z.y / r).acos() * self.power;
+            let phi = z.z.atan2(z.x) * self.power;
+            dr = self.power * r.powf(self.power - 1.0) * dr + 1.0;
+            let zr = r.powf(self.power);
+            z = Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            ) * zr
+                + p;
+            r = z.length();
+        }
+        if r <= BAILOUT {
+            return 0.0;
+        }
+        0.5 * r.ln() * r / dr
+    }
+
+    /// 从`origin`沿单位向量`direction`前进，返回碰到表面时走过的距离。
+    pub fn march(&self, origin: Vec3, direction: Vec3) -> Option<f64> {
+        let mut t = 0.0;
+        for _ in 0..MAX_STEPS {
+            let distance = self.distance(origin + direction * t);
+            if distance < HIT_EPSILON * t.max(1.0) {
+                return Some(t);
+            }
+            t += distance;
+            if t > MAX_DISTANCE {
+                break;
+            }
+        }
+        None
+    }
+
+    /// 表面在`p`处的法向量，用距离估计的中心差分近似梯度。
+    fn normal(&self, p: Vec3, h: f64) -> Vec3 {
+        let dx = Vec3::new(h, 0.0, 0.0);
+        let dy = Vec3::new(0.0, h, 0.0);
+        let dz = Vec3::new(0.0, 0.0, h);
+        Vec3::new(
+            self.distance(p + dx) - self.distance(p - dx),
+            self.distance(p + dy) - self.distance(p - dy),
+            self.distance(p + dz) - self.distance(p - dz),
+        )
+        .normalize()
+    }
+
+    /// 环境光遮蔽：沿法向量走几步，如果距离估计比走过的距离小，说明附近有
+    /// 别的表面挡住了光。返回 0（完全被挡住）到 1（完全没有遮挡）。
+    fn ambient_occlusion(&self, p: Vec3, normal: Vec3) -> f64 {
+        let mut occlusion = 0.0;
+        let mut weight = 1.0;
+        for i in 1..=AO_SAMPLES {
+            let step = AO_STEP * i as f64;
+            occlusion += weight * (step - self.distance(p + normal * step)).max(0.0);
+            weight *= 0.5;
+        }
+        (1.0 - occlusion * 5.0).clamp(0.0, 1.0)
+    }
+
+    /// 一个像素的颜色。
+    fn shade(&self, camera: &Camera, direction: Vec3, screen: Complex<f64>) -> [u8; 3] {
+        let Some(t) = self.march(camera.position, direction) else {
+            // 背景是从上到下变亮的深蓝色
+            let v = 0.5 - screen.im * 0.25;
+            return to_rgb(Vec3::new(0.05, 0.07, 0.12) * v * 2.0);
+        };
+        let p = camera.position + direction * t;
+        let normal = self.normal(p, HIT_EPSILON * t.max(1.0));
+        let light = Vec3::new(0.6, 0.8, -0.4).normalize();
+        let diffuse = normal.dot(light).max(0.0);
+        // 从相机方向补一点光，让背光的一面也看得清
+        let fill = normal.dot(-direction).max(0.0) * 0.3;
+        let ao = self.ambient_occlusion(p, normal);
+        // 按离原点的距离在两种颜色之间过渡，让凹处和凸处颜色不同
+        let k = (p.length() / 1.2).clamp(0.0, 1.0);
+        let base = Vec3::new(0.9, 0.55, 0.25) * k + Vec3::new(0.35, 0.2, 0.45) * (1.0 - k);
+        to_rgb(base * ((0.15 + diffuse * 0.85 + fill) * ao))
+    }
+}
+
+/// 把 0 到 1 之间的线性颜色做伽马校正后转成字节。
+fn to_rgb(color: Vec3) -> [u8; 3] {
+    let byte = |x: f64| (x.clamp(0.0, 1.0).powf(1.0 / 2.2) * 255.0).round() as u8;
+    [byte(color.x), byte(color.y), byte(color.z)]
+}
+
+/// 用光线步进（ray marching）渲染曼德勃罗球，结果写入 RGB 缓冲区`pixels`。
+///
+/// `band`里的坐标是屏幕坐标，整幅图像用`screen_corners`，
+/// 所以可以和`render_band`一样交给`render_bands`分条带渲染。
+pub fn render_mandelbulb(pixels: &mut [u8], band: Band, camera: &Camera, bulb: &Bulb) {
+    let bounds = band.size();
+    assert_eq!(pixels.len(), bounds.0 * bounds.1 * 3);
+
+    // 取像素的中心，而不是左上角
+    let pixel_size = band.pixel_size();
+    let half_pixel = Complex::new(pixel_size.re, -pixel_size.im) / 2.0;
+    for row in 0..bounds.1 {
+        for column in 0..bounds.0 {
+            let screen = band.point(column, row) + half_pixel;
+            let color = bulb.shade(camera, camera.ray(screen), screen);
+            let offset = (row * bounds.0 + column) * 3;
+            pixels[offset..offset + 3].copy_from_slice(&color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrency::draw::render_bands;
+
+    #[test]
+    fn test_parse_vec3() {
+        assert_eq!("1, -2.5,3".parse(), Ok(Vec3::new(1.0, -2.5, 3.0)));
+        assert!("1,2".parse::<Vec3>().is_err());
+    }
+
+    #[test]
+    fn test_distance() {
+        let bulb = Bulb::default();
+        assert_eq!(bulb.distance(Vec3::new(0.0, 0.0, 0.0)), 0.0);
+        assert_eq!(bulb.distance(Vec3::new(0.1, 0.2, 0.0)), 0.0);
+        // 曼德勃罗球大约在半径 1.2 以内，距离估计偏小但不会差得太多
+        let d = bulb.distance(Vec3::new(3.0, 0.0, 0.0));
+        assert!(d > 0.5 && d < 2.0, "{}", d);
+        assert!(bulb.distance(Vec3::new(1.5, 0.0, 0.0)) < d);
+    }
+
+    #[test]
+    fn test_march() {
+        let bulb = Bulb::default();
+        let origin = Vec3::new(0.0, 0.0, -3.0);
+        let t = bulb.march(origin, Vec3::new(0.0, 0.0, 1.0)).unwrap();
+        assert!(t > 1.5 && t < 2.5, "{}", t);
+        assert_eq!(bulb.march(origin, Vec3::new(0.0, 1.0, 0.0)), None);
+    }
+
+    #[test]
+    fn test_camera_check() {
+        assert_eq!(Camera::default().check(), Ok(()));
+        let camera = Camera::default();
+        let same = Camera {
+            target: camera.position,
+            ..camera
+        };
+        assert!(same.check().is_err());
+        for fov in [0.0, 180.0, 270.0, f64::NAN] {
+            assert!(Camera { fov, ..camera }.check().is_err(), "{}", fov);
+        }
+        assert_eq!(
+            Camera {
+                fov: 179.0,
+                ..camera
+            }
+            .check(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_camera_ray() {
+        let camera = Camera {
+            position: Vec3::new(0.0, 0.0, -3.0),
+            target: Vec3::new(0.0, 0.0, 0.0),
+            fov: 90.0,
+        };
+        assert_eq!(camera.ray(Complex::new(0.0, 0.0)), Vec3::new(0.0, 0.0, 1.0));
+        // 视角 90 度，屏幕顶边的光线朝上偏 45 度，右边的光线朝右偏
+        let top = camera.ray(Complex::new(0.0, 1.0));
+        assert!((top.y - top.z).abs() < 1e-12 && top.y > 0.0);
+        assert!(camera.ray(Complex::new(1.0, 0.0)).x > 0.0);
+    }
+
+    #[test]
+    fn test_render_mandelbulb() {
+        let bounds = (32, 24);
+        let (upper_left, lower_right) = screen_corners(bounds);
+        let camera = Camera::default();
+        let bulb = Bulb::default();
+        let mut pixels = vec![0; bounds.0 * bounds.1 * 3];
+        render_mandelbulb(
+            &mut pixels,
+            Band::whole(bounds, upper_left, lower_right),
+            &camera,
+            &bulb,
+        );
+        let pixel = |column: usize, row: usize| {
+            let offset = (row * bounds.0 + column) * 3;
+            [pixels[offset], pixels[offset + 1], pixels[offset + 2]]
+        };
+        // 中间是曼德勃罗球，角上是背景
+        let (center, corner) = (pixel(16, 12), pixel(0, 0));
+        assert!(center[0] > corner[0], "{:?} {:?}", center, corner);
+
+        // 分条带渲染的结果完全相同
+        let mut banded = vec![0; pixels.len()];
+        render_bands(
+            &mut banded,
+            bounds,
+            upper_left,
+            lower_right,
+            3,
+            |pixels, band| render_mandelbulb(pixels, band, &camera, &bulb),
+        );
+        assert_eq!(banded, pixels);
+    }
+}
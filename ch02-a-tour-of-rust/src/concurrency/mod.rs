@@ -1,3 +1,25 @@
+pub mod area;
+pub mod buddhabrot;
+pub mod contour;
+pub mod distributed;
 pub mod draw;
+pub mod explorer;
+pub mod formula;
+pub mod heightmap;
+pub mod lyapunov;
 pub mod mandelbrot;
+pub mod mandelbulb;
+pub mod newton;
+pub mod nucleus;
+pub mod palette;
 pub mod parse;
+pub mod preview;
+pub mod probe;
+pub mod rays;
+pub mod render_cache;
+pub mod scene;
+pub mod stats;
+pub mod threads;
+pub mod tiles;
+pub mod trap;
+pub mod viewport;
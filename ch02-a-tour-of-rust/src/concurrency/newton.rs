@@ -0,0 +1,341 @@
+use crate::concurrency::draw::Band;
+use crate::concurrency::palette::hsv;
+use crate::concurrency::parse::parse_complex;
+use num::Complex;
+use std::str::FromStr;
+
+/// 牛顿法最多迭代的次数。
+const NEWTON_LIMIT: usize = 64;
+
+/// 离某个根的距离小于它时认为已经收敛到这个根。
+const ROOT_TOLERANCE: f64 = 1e-6;
+
+/// 解析多项式时允许的最高次数。系数按次数存放，太高的次数会分配巨大的数组，
+/// 而且每个根只分到一种色相，次数再高也分辨不出来了。
+pub const MAX_DEGREE: usize = 64;
+
+/// 复系数多项式，`coefficients[k]`是`z^k`的系数。
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polynomial {
+    coefficients: Vec<Complex<f64>>,
+}
+
+impl Polynomial {
+    /// 从系数创建多项式，`coefficients[k]`是`z^k`的系数。末尾为零的系数会被去掉。
+    pub fn new(mut coefficients: Vec<Complex<f64>>) -> Polynomial {
+        while coefficients
+            .last()
+            .is_some_and(|c| *c == Complex::new(0.0, 0.0))
+        {
+            coefficients.pop();
+        }
+        Polynomial { coefficients }
+    }
+
+    /// 多项式的次数。零多项式的次数算作 0。
+    pub fn degree(&self) -> usize {
+        self.coefficients.len().saturating_sub(1)
+    }
+
+    /// 用秦九韶（Horner）算法求`p(z)`。
+    pub fn eval(&self, z: Complex<f64>) -> Complex<f64> {
+        self.coefficients
+            .iter()
+            .rev()
+            .fold(Complex::new(0.0, 0.0), |acc, &c| acc * z + c)
+    }
+
+    /// 导数`p'(z)`。
+    pub fn derivative(&self) -> Polynomial {
+        Polynomial::new(
+            self.coefficients
+                .iter()
+                .enumerate()
+                .skip(1)
+                .map(|(k, &c)| c * k as f64)
+                .collect(),
+        )
+    }
+
+    /// 用 Durand–Kerner 方法同时求出所有的根（计入重数）。
+    pub fn roots(&self) -> Vec<Complex<f64>> {
+        let n = self.degree();
+        if n == 0 {
+            return Vec::new();
+        }
+        // 先化成首一多项式
+        let leading = self.coefficients[n];
+        let monic = Polynomial::new(self.coefficients.iter().map(|&c| c / leading).collect());
+
+        let seed = Complex::new(0.4, 0.9);
+        let mut roots: Vec<Complex<f64>> = (0..n).map(|k| seed.powu(k as u32)).collect();
+        for _ in 0..500 {
+            let mut largest_step: f64 = 0.0;
+            for i in 0..n {
+                let denominator = (0..n)
+                    .filter(|&j| j != i)
+                    .fold(Complex::new(1.0, 0.0), |acc, j| acc * (roots[i] - roots[j]));
+                let step = monic.eval(roots[i]) / denominator;
+                if step.is_finite() {
+                    roots[i] -= step;
+                    largest_step = largest_step.max(step.norm());
+                }
+            }
+            if largest_step < 1e-14 {
+                break;
+            }
+        }
+        roots
+    }
+}
+
+/// 解析形如`z^3 - 1`、`2z^2 + (1+2i)*z - i`的多项式。
+///
+/// 每一项是一个可选的系数，后面跟着可选的`z`或`z^n`，系数和`z`之间可以有`*`。
+/// 系数可以是实数、纯虚数（`2i`），或者用括号括起来的任意复数。
+impl FromStr for Polynomial {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Polynomial, String> {
+        let mut coefficients = Vec::new();
+        for (sign, term) in split_terms(s)? {
+            let (coefficient, power) = parse_term(&term)?;
+            if coefficients.len() <= power {
+                coefficients.resize(power + 1, Complex::new(0.0, 0.0));
+            }
+            coefficients[power] += sign * coefficient;
+        }
+        let polynomial = Polynomial::new(coefficients);
+        if polynomial.degree() == 0 {
+            return Err(format!("{:?} is not a polynomial of degree 1 or more", s));
+        }
+        Ok(polynomial)
+    }
+}
+
+/// 在括号之外的`+`和`-`处把多项式拆成项，返回每一项的符号和去掉空白的文本。
+/// 指数里的符号（例如`1e-3`）不算。
+fn split_terms(s: &str) -> Result<Vec<(f64, String)>, String> {
+    let mut terms = Vec::new();
+    let mut sign = 1.0;
+    let mut term = String::new();
+    let mut depth = 0;
+    for c in s.chars().filter(|c| !c.is_whitespace()) {
+        let in_exponent = term.ends_with(['e', 'E'])
+            && term[..term.len() - 1].ends_with(|c: char| c.is_ascii_digit() || c == '.');
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Err(format!("unbalanced ')' in {:?}", s)),
+            ')' => depth -= 1,
+            '+' | '-' | '\u{2212}' if depth == 0 && !in_exponent => {
+                if !term.is_empty() {
+                    terms.push((sign, term));
+                    term = String::new();
+                } else if !terms.is_empty() {
+                    return Err(format!("missing term before {:?} in {:?}", c, s));
+                }
+                sign = if c == '+' { 1.0 } else { -1.0 };
+                continue;
+            }
+            _ => {}
+        }
+        term.push(c);
+    }
+    if depth != 0 {
+        return Err(format!("unbalanced '(' in {:?}", s));
+    }
+    if term.is_empty() {
+        return Err(format!("missing term at the end of {:?}", s));
+    }
+    terms.push((sign, term));
+    Ok(terms)
+}
+
+/// 解析一项，例如`3z^2`、`(1+2i)*z`、`-1`里的`1`，返回系数和次数。
+fn parse_term(term: &str) -> Result<(Complex<f64>, usize), String> {
+    let (coefficient, power) = match term.find('z') {
+        None => (term, 0),
+        Some(index) => {
+            let power = match &term[index + 1..] {
+                "" => 1,
+                rest => rest
+                    .strip_prefix('^')
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(|| format!("invalid power {:?} in term {:?}", rest, term))?,
+            };
+            if power > MAX_DEGREE {
+                return Err(format!(
+                    "power {} in term {:?} is larger than {}",
+                    power, term, MAX_DEGREE
+                ));
+            }
+            let coefficient = &term[..index];
+            (coefficient.strip_suffix('*').unwrap_or(coefficient), power)
+        }
+    };
+    let coefficient = match coefficient {
+        "" if power > 0 => Complex::new(1.0, 0.0),
+        text => parse_complex(text)
+            .map_err(|err| format!("invalid coefficient {:?} in term {:?}: {}", text, term, err))?,
+    };
+    Ok((coefficient, power))
+}
+
+/// 从`z`出发用牛顿法求`polynomial`的根，最多迭代`limit`次。
+///
+/// 如果收敛到了`roots`中的某个根，返回`Some((k, i))`，其中`k`是这个根在
+/// `roots`中的下标，`i`是迭代次数；如果没有收敛（或者遇到了导数为零的点），
+/// 返回`None`。
+pub fn newton_basin(
+    mut z: Complex<f64>,
+    polynomial: &Polynomial,
+    derivative: &Polynomial,
+    roots: &[Complex<f64>],
+    limit: usize,
+) -> Option<(usize, usize)> {
+    for i in 0..limit {
+        if let Some(k) = roots
+            .iter()
+            .position(|&root| (z - root).norm_sqr() < ROOT_TOLERANCE * ROOT_TOLERANCE)
+        {
+            return Some((k, i));
+        }
+        let step = polynomial.eval(z) / derivative.eval(z);
+        if !step.is_finite() {
+            return None;
+        }
+        z -= step;
+    }
+    None
+}
+
+/// 把条带`band`的牛顿分形渲染到 RGB 缓冲区`pixels`里。
+///
+/// 其它参数的含义和`render_band`相同。每个根分配一种色相，收敛到这个根的点
+/// 用这种颜色，迭代次数越多颜色越暗；不收敛的点是黑色。
+pub fn render_newton(pixels: &mut [u8], band: Band, polynomial: &Polynomial) {
+    let bounds = band.size();
+    assert_eq!(pixels.len(), bounds.0 * bounds.1 * 3);
+
+    let derivative = polynomial.derivative();
+    let roots = polynomial.roots();
+    for row in 0..bounds.1 {
+        for column in 0..bounds.0 {
+            let point = band.point(column, row);
+            let color = match newton_basin(point, polynomial, &derivative, &roots, NEWTON_LIMIT) {
+                None => [0, 0, 0],
+                Some((k, i)) => {
+                    let hue = k as f64 / roots.len() as f64;
+                    hsv(hue, 0.8, 0.92f64.powi(i as i32).max(0.15))
+                }
+            };
+            let offset = (row * bounds.0 + column) * 3;
+            pixels[offset..offset + 3].copy_from_slice(&color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c(re: f64, im: f64) -> Complex<f64> {
+        Complex { re, im }
+    }
+
+    #[test]
+    fn test_parse_polynomial() {
+        let cubic: Polynomial = "z^3 - 1".parse().unwrap();
+        assert_eq!(
+            cubic,
+            Polynomial::new(vec![c(-1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0), c(1.0, 0.0)])
+        );
+
+        let p: Polynomial = "2z^2 + (1+2i)*z - i + 1e-3".parse().unwrap();
+        assert_eq!(
+            p,
+            Polynomial::new(vec![c(1e-3, -1.0), c(1.0, 2.0), c(2.0, 0.0)])
+        );
+
+        assert_eq!(
+            "-z + z^2".parse(),
+            Ok(Polynomial::new(vec![
+                c(0.0, 0.0),
+                c(-1.0, 0.0),
+                c(1.0, 0.0)
+            ]))
+        );
+        assert!("3".parse::<Polynomial>().is_err());
+        assert!("z^".parse::<Polynomial>().is_err());
+        assert!("z^2 +".parse::<Polynomial>().is_err());
+        assert!("z^2 + + 1".parse::<Polynomial>().is_err());
+        assert!("(1+2i z".parse::<Polynomial>().is_err());
+        assert!("x^2".parse::<Polynomial>().is_err());
+    }
+
+    #[test]
+    fn test_parse_degree_limit() {
+        let highest = format!("z^{} - 1", MAX_DEGREE);
+        assert_eq!(
+            highest.parse::<Polynomial>().map(|p| p.degree()),
+            Ok(MAX_DEGREE)
+        );
+        // 不能溢出，也不能分配巨大的系数数组
+        for power in [MAX_DEGREE + 1, 10_000_000_000, usize::MAX] {
+            let text = format!("z^{}", power);
+            assert_eq!(
+                text.parse::<Polynomial>(),
+                Err(format!(
+                    "power {} in term {:?} is larger than {}",
+                    power, text, MAX_DEGREE
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn test_eval_and_derivative() {
+        let p: Polynomial = "z^3 - 2z + 1".parse().unwrap();
+        assert_eq!(p.eval(c(2.0, 0.0)), c(5.0, 0.0));
+        assert_eq!(p.derivative(), "3z^2 - 2".parse().unwrap());
+    }
+
+    #[test]
+    fn test_roots() {
+        let p: Polynomial = "z^3 - 1".parse().unwrap();
+        let roots = p.roots();
+        assert_eq!(roots.len(), 3);
+        for root in &roots {
+            assert!(p.eval(*root).norm() < 1e-10);
+        }
+        // 三个根互不相同
+        assert!((roots[0] - roots[1]).norm() > 0.5);
+        assert!((roots[1] - roots[2]).norm() > 0.5);
+        assert!((roots[0] - roots[2]).norm() > 0.5);
+    }
+
+    #[test]
+    fn test_newton_basin() {
+        let p: Polynomial = "z^2 - 1".parse().unwrap();
+        let roots = p.roots();
+        let derivative = p.derivative();
+        let right = roots.iter().position(|r| r.re > 0.0).unwrap();
+        let (k, _) = newton_basin(c(3.0, 0.5), &p, &derivative, &roots, 64).unwrap();
+        assert_eq!(k, right);
+        // 原点处导数为零，牛顿法无法继续
+        assert_eq!(newton_basin(c(0.0, 0.0), &p, &derivative, &roots, 64), None);
+    }
+
+    #[test]
+    fn test_render_newton() {
+        let bounds = (6, 4);
+        let mut pixels = vec![0; bounds.0 * bounds.1 * 3];
+        let p: Polynomial = "z^3 - 1".parse().unwrap();
+        render_newton(
+            &mut pixels,
+            Band::whole(bounds, c(-2.0, 2.0), c(2.0, -2.0)),
+            &p,
+        );
+        assert!(pixels.iter().any(|&p| p > 0));
+    }
+}
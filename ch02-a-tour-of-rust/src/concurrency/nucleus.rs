@@ -0,0 +1,226 @@
+use crate::concurrency::viewport::Viewport;
+use num::Complex;
+
+/// 轨迹回到起点的距离小于它时，认为找到了周期轨道。
+const CYCLE_TOLERANCE: f64 = 1e-9;
+
+/// 牛顿法最多迭代的次数。
+const NEWTON_STEPS: usize = 64;
+
+/// 牛顿法的步长相对于`c`小于它时认为已经收敛。
+const NEWTON_TOLERANCE: f64 = 1e-14;
+
+/// 小曼德勃罗集（minibrot）的核心：周期为`period`的周期点`z = 0`对应的`c`。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Nucleus {
+    /// 核心的位置，`z`从 0 开始迭代`period`次后回到 0。
+    pub center: Complex<f64>,
+    pub period: usize,
+    /// 小曼德勃罗集相对于整个曼德勃罗集的大小和方向：它近似于把整个集合乘以
+    /// `size`再平移到`center`。参见`minibrot_size`。
+    pub size: Complex<f64>,
+}
+
+impl Nucleus {
+    /// 返回一个刚好能装下这个小曼德勃罗集的视口，宽高比和`bounds`相同。
+    ///
+    /// 整个集合大约在以 -0.75 为中心、半径 1.25 的圆里，所以无论`size`
+    /// 朝哪个方向旋转，以`center - 0.75 * size`为中心、边长`3|size|`的
+    /// 正方形都能装下它，再按宽高比把较长的一边加长。
+    pub fn viewport(&self, bounds: (usize, usize)) -> Viewport {
+        let middle = self.center + self.size * -0.75;
+        let side = 3.0 * self.size.norm();
+        let aspect = bounds.0 as f64 / bounds.1 as f64;
+        let (width, height) = if aspect >= 1.0 {
+            (side * aspect, side)
+        } else {
+            (side, side / aspect)
+        };
+        let half = Complex {
+            re: width / 2.0,
+            im: height / 2.0,
+        };
+        Viewport::new(middle - half.conj(), middle + half.conj())
+    }
+}
+
+/// 估计`c`附近的小曼德勃罗集的周期，最多迭代`limit`次。
+///
+/// 如果`c`逃逸了，就用原子域（atom domain）的方法：返回逃逸之前`|z_n|`最小的`n`。
+/// 小曼德勃罗集周围的原子域比它本身大得多，粗略的位置就够了。
+/// 如果`c`没有逃逸，它多半在某个双曲分量内部，这时返回`attracting_period`；
+/// 找不到周期轨道时仍然退回到原子域的结果。
+pub fn estimate_period(c: Complex<f64>, limit: usize) -> usize {
+    let mut z = Complex { re: 0.0, im: 0.0 };
+    let mut smallest = f64::INFINITY;
+    let mut period = 1;
+    for n in 1..=limit {
+        z = z * z + c;
+        let norm = z.norm_sqr();
+        if norm > 4.0 {
+            return period;
+        }
+        if norm < smallest {
+            smallest = norm;
+            period = n;
+        }
+    }
+    attracting_period(c, limit).unwrap_or(period)
+}
+
+/// 如果`c`的轨迹在`limit`次迭代之内收敛到了一个吸引周期轨道，返回它的周期。
+///
+/// 先迭代`limit`次让轨迹靠近周期轨道，再看它最多`limit`次之内能否回到
+/// 同一个位置附近。逃逸的点或者收敛太慢的点返回`None`。
+pub fn attracting_period(c: Complex<f64>, limit: usize) -> Option<usize> {
+    let mut z = Complex { re: 0.0, im: 0.0 };
+    for _ in 0..limit {
+        z = z * z + c;
+        if z.norm_sqr() > 4.0 {
+            return None;
+        }
+    }
+    let start = z;
+    for p in 1..=limit {
+        z = z * z + c;
+        if (z - start).norm() < CYCLE_TOLERANCE {
+            return Some(p);
+        }
+    }
+    None
+}
+
+/// 从`guess`出发，用牛顿法求周期为`period`的核心，也就是方程`z_p(c) = 0`的根。
+///
+/// 迭代`z`的同时跟踪它对`c`的导数`dc' = 2 * z * dc + 1`。如果牛顿法不收敛就返回
+/// `None`。收敛到的核心的周期可能是`period`的因数（例如从周期 6 收敛到了周期 3
+/// 的核心），这时返回的`Nucleus::period`是真正的周期。
+pub fn find_nucleus(guess: Complex<f64>, period: usize) -> Option<Nucleus> {
+    if period == 0 {
+        return None;
+    }
+    let mut c = guess;
+    for _ in 0..NEWTON_STEPS {
+        let mut z = Complex { re: 0.0, im: 0.0 };
+        let mut dc = Complex { re: 0.0, im: 0.0 };
+        for _ in 0..period {
+            dc = 2.0 * z * dc + 1.0;
+            z = z * z + c;
+        }
+        let step = z / dc;
+        if !step.is_finite() {
+            return None;
+        }
+        c -= step;
+        if step.norm() <= NEWTON_TOLERANCE * c.norm().max(1.0) {
+            let period = exact_period(c, period);
+            return Some(Nucleus {
+                center: c,
+                period,
+                size: minibrot_size(c, period),
+            });
+        }
+    }
+    None
+}
+
+/// 核心`c`真正的周期：`z`第一次回到 0 附近的迭代次数，不超过`period`。
+fn exact_period(c: Complex<f64>, period: usize) -> usize {
+    let mut z = Complex { re: 0.0, im: 0.0 };
+    let epsilon = 1e-9 * c.norm().max(1.0);
+    for n in 1..period {
+        z = z * z + c;
+        if z.norm() < epsilon {
+            return n;
+        }
+    }
+    period
+}
+
+/// 估计核心为`c`、周期为`period`的小曼德勃罗集的大小和方向。
+///
+/// 返回的复数`s`满足：小曼德勃罗集近似于整个曼德勃罗集乘以`s`再平移到`c`，
+/// 所以`|s|`是相对大小，`arg(s)`是旋转的角度。主心形（`c = 0`，周期 1）
+/// 的大小是 1。
+pub fn minibrot_size(c: Complex<f64>, period: usize) -> Complex<f64> {
+    let mut z = Complex { re: 0.0, im: 0.0 };
+    let mut l = Complex { re: 1.0, im: 0.0 };
+    let mut b = Complex { re: 1.0, im: 0.0 };
+    for _ in 1..period {
+        z = z * z + c;
+        l = 2.0 * z * l;
+        b += 1.0 / l;
+    }
+    1.0 / (b * l * l)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c(re: f64, im: f64) -> Complex<f64> {
+        Complex { re, im }
+    }
+
+    /// 实轴上周期为 3 的“飞机”小曼德勃罗集的核心。
+    const AIRPLANE: f64 = -1.754_877_666_246_693;
+
+    #[test]
+    fn test_estimate_period() {
+        // 双曲分量内部：周期轨道的周期
+        assert_eq!(estimate_period(c(0.1, 0.1), 1000), 1);
+        assert_eq!(estimate_period(c(-1.05, 0.05), 1000), 2);
+        assert_eq!(estimate_period(c(-1.755, 0.001), 1000), 3);
+        // 集合外面、在“飞机”的原子域里
+        assert_eq!(estimate_period(c(AIRPLANE, 0.02), 1000), 3);
+    }
+
+    #[test]
+    fn test_attracting_period() {
+        assert_eq!(attracting_period(c(-1.05, 0.05), 1000), Some(2));
+        assert_eq!(attracting_period(c(AIRPLANE, 0.02), 1000), None);
+        assert_eq!(attracting_period(c(1.0, 0.0), 1000), None);
+    }
+
+    #[test]
+    fn test_find_nucleus() {
+        let nucleus = find_nucleus(c(-1.75, 0.01), 3).unwrap();
+        assert_eq!(nucleus.period, 3);
+        assert!((nucleus.center - c(AIRPLANE, 0.0)).norm() < 1e-12);
+
+        let bulb = find_nucleus(c(-0.9, 0.1), 2).unwrap();
+        assert!((bulb.center - c(-1.0, 0.0)).norm() < 1e-12);
+
+        // 周期 1 的核心也是周期 2 方程的根，应该报告真正的周期
+        let cardioid = find_nucleus(c(0.01, 0.0), 2).unwrap();
+        assert!(cardioid.center.norm() < 1e-12);
+        assert_eq!(cardioid.period, 1);
+
+        assert_eq!(find_nucleus(c(0.0, 0.0), 0), None);
+    }
+
+    #[test]
+    fn test_minibrot_size() {
+        assert_eq!(minibrot_size(c(0.0, 0.0), 1), c(1.0, 0.0));
+        assert_eq!(minibrot_size(c(-1.0, 0.0), 2), c(0.5, 0.0));
+        // “飞机”大约从 -1.786 延伸到 -1.745，是整个集合（宽约 2.25）的 1/50 左右
+        let size = minibrot_size(c(AIRPLANE, 0.0), 3);
+        assert!(size.re > 0.015 && size.re < 0.025, "{}", size);
+        assert!(size.im.abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_viewport() {
+        let nucleus = Nucleus {
+            center: c(0.0, 0.0),
+            period: 1,
+            size: c(1.0, 0.0),
+        };
+        let viewport = nucleus.viewport((400, 200));
+        assert_eq!(viewport.upper_left, c(-3.75, 1.5));
+        assert_eq!(viewport.lower_right, c(2.25, -1.5));
+        let tall = nucleus.viewport((100, 200));
+        assert_eq!(tall.width(), 3.0);
+        assert_eq!(tall.height(), 6.0);
+    }
+}
@@ -0,0 +1,106 @@
+/// 把`t`（0 到 1 之间）映射成一个从深蓝经过橙黄到白的平滑渐变色。
+///
+/// 超出范围的`t`会被截断到 0 到 1 之间。
+pub fn gradient(t: f64) -> [u8; 3] {
+    let t = t.clamp(0.0, 1.0);
+    let s = 1.0 - t;
+    let r = 9.0 * s * t * t * t;
+    let g = 15.0 * s * s * t * t;
+    let b = 8.5 * s * s * s * t;
+    [to_byte(r), to_byte(g), to_byte(b)]
+}
+
+/// 把`t`（0 到 1 之间）映射成从黑经过红、黄到白的“热度”色，亮度单调增加。
+pub fn heat(t: f64) -> [u8; 3] {
+    let t = t.clamp(0.0, 1.0) * 3.0;
+    [to_byte(t), to_byte(t - 1.0), to_byte(t - 2.0)]
+}
+
+/// 把 HSV 颜色转换成 RGB。`hue`、`saturation`和`value`都在 0 到 1 之间，
+/// `hue`超出范围时按周期取模。
+pub fn hsv(hue: f64, saturation: f64, value: f64) -> [u8; 3] {
+    let h = hue.rem_euclid(1.0) * 6.0;
+    let c = value * saturation;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as usize {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = value - c;
+    [to_byte(r + m), to_byte(g + m), to_byte(b + m)]
+}
+
+/// 发散色：`t`（-1 到 1 之间）为 0 时是`middle`，向两端分别线性过渡到
+/// `negative`和`positive`，适合正负号有不同含义的量。
+///
+/// 超出范围的`t`会被截断，NaN 当作 0。
+pub fn diverging(t: f64, negative: [u8; 3], middle: [u8; 3], positive: [u8; 3]) -> [u8; 3] {
+    let t = if t.is_nan() { 0.0 } else { t.clamp(-1.0, 1.0) };
+    let end = if t < 0.0 { negative } else { positive };
+    let s = t.abs();
+    let mix = |i: usize| (middle[i] as f64 * (1.0 - s) + end[i] as f64 * s).round() as u8;
+    [mix(0), mix(1), mix(2)]
+}
+
+/// 按`escape_time`的结果着色：集合内部（`None`）是黑色，
+/// 逃逸的点按迭代次数在`gradient`上取色。
+pub fn escape_color(count: Option<usize>, limit: usize) -> [u8; 3] {
+    match count {
+        None => [0, 0, 0],
+        Some(count) => gradient((count as f64 / limit as f64).sqrt()),
+    }
+}
+
+fn to_byte(x: f64) -> u8 {
+    (x.clamp(0.0, 1.0) * 255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gradient() {
+        assert_eq!(gradient(0.0), [0, 0, 0]);
+        assert_eq!(gradient(1.0), [0, 0, 0]);
+        assert_eq!(gradient(-1.0), gradient(0.0));
+        let [r, g, b] = gradient(0.5);
+        assert!(r > 0 && g > 0 && b > 0);
+    }
+
+    #[test]
+    fn test_heat() {
+        assert_eq!(heat(0.0), [0, 0, 0]);
+        assert_eq!(heat(1.0 / 3.0), [255, 0, 0]);
+        assert_eq!(heat(1.0), [255, 255, 255]);
+    }
+
+    #[test]
+    fn test_hsv() {
+        assert_eq!(hsv(0.0, 1.0, 1.0), [255, 0, 0]);
+        assert_eq!(hsv(1.0 / 3.0, 1.0, 1.0), [0, 255, 0]);
+        assert_eq!(hsv(2.0 / 3.0, 1.0, 1.0), [0, 0, 255]);
+        assert_eq!(hsv(1.0, 1.0, 1.0), hsv(0.0, 1.0, 1.0));
+        assert_eq!(hsv(0.5, 0.0, 1.0), [255, 255, 255]);
+    }
+
+    #[test]
+    fn test_diverging() {
+        let (blue, white, red) = ([0, 0, 255], [255, 255, 255], [255, 0, 0]);
+        assert_eq!(diverging(0.0, blue, white, red), white);
+        assert_eq!(diverging(-1.0, blue, white, red), blue);
+        assert_eq!(diverging(2.0, blue, white, red), red);
+        assert_eq!(diverging(0.5, blue, white, red), [255, 128, 128]);
+        assert_eq!(diverging(f64::NAN, blue, white, red), white);
+    }
+
+    #[test]
+    fn test_escape_color() {
+        assert_eq!(escape_color(None, 255), [0, 0, 0]);
+        assert_ne!(escape_color(Some(10), 255), [0, 0, 0]);
+    }
+}
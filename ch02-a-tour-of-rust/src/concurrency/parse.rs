@@ -1,30 +1,348 @@
 use num::Complex;
+use std::fmt;
 use std::str::FromStr;
 
 /// 将`s`解析为一个坐标对，例如`"400x6000"`或者`"1.0,0.5"`
 ///
 /// 确切地说，`s`的形式应该是 <left><sep><right>，其中 <sep> 是由
 /// `separator`参数指定的字符，<left> 和 <right> 都是可以被`T::from_str`
-/// 解析的字符串，`separator`必须是个 ASCII 字符。
+/// 解析的字符串。
 ///
 /// 如果`s`的格式正确，就返回`Some<(x, y)>`。
-/// 如果不能正确解析，就返回`None`。
+/// 如果不能正确解析，就返回`None`。需要知道出错原因或者更灵活的分隔符时，
+/// 请使用`parse_tuple`。
 pub fn parse_pair<T: FromStr>(s: &str, separator: char) -> Option<(T, T)> {
-    match s.find(separator) {
-        None => None,
-        // 可以直接match俩
-        Some(index) => match (T::from_str(&s[..index]), T::from_str(&s[index + 1..])) {
-            (Ok(l), Ok(r)) => Some((l, r)),
-            _ => None,
-        },
+    parse_tuple::<T, 2>(s, separator, false)
+        .ok()
+        .map(|[l, r]| (l, r))
+}
+
+/// `parse_tuple`使用的分隔符。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Separator<'a> {
+    /// 单个字符，例如`'x'`。
+    Char(char),
+    /// 一个非空字符串，例如`", "`或者`" x "`。
+    Str(&'a str),
+    /// 字符集合中的任意一个字符，例如`&[',', ';']`。
+    AnyOf(&'a [char]),
+}
+
+impl From<char> for Separator<'_> {
+    fn from(c: char) -> Self {
+        Separator::Char(c)
+    }
+}
+
+impl<'a> From<&'a str> for Separator<'a> {
+    fn from(s: &'a str) -> Self {
+        Separator::Str(s)
+    }
+}
+
+impl<'a> From<&'a [char]> for Separator<'a> {
+    fn from(chars: &'a [char]) -> Self {
+        Separator::AnyOf(chars)
+    }
+}
+
+/// `parse_tuple`返回的错误。
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseTupleError {
+    /// 字段的个数不对。
+    WrongCount { expected: usize, found: usize },
+    /// 第`index`个字段（从 0 开始）不能被`T::from_str`解析。
+    InvalidField { index: usize, field: String },
+}
+
+impl fmt::Display for ParseTupleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseTupleError::WrongCount { expected, found } => {
+                write!(f, "expected {} fields, found {}", expected, found)
+            }
+            ParseTupleError::InvalidField { index, field } => {
+                write!(f, "invalid field {}: {:?}", index + 1, field)
+            }
+        }
     }
 }
 
-/// 把一对逗号分隔的浮点数解析为一个复数
-pub fn parse_complex(s: &str) -> Option<Complex<f64>> {
-    match parse_pair(s, ',') {
-        Some((re, im)) => Some(Complex { re, im }),
-        None => None,
+impl std::error::Error for ParseTupleError {}
+
+/// 把`s`解析为`N`个用`separator`分隔的值，例如`"255,128,0"`或者`"10 x 20"`。
+///
+/// `separator`可以是一个字符、一个字符串或者一个字符集合（参见`Separator`）。
+/// 如果`trim`为真，每个字段在解析之前都会去掉首尾的空白。
+pub fn parse_tuple<'a, T: FromStr, const N: usize>(
+    s: &str,
+    separator: impl Into<Separator<'a>>,
+    trim: bool,
+) -> Result<[T; N], ParseTupleError> {
+    let fields: Vec<&str> = match separator.into() {
+        Separator::Char(c) => s.split(c).collect(),
+        Separator::Str(sep) => {
+            assert!(!sep.is_empty(), "separator must not be empty");
+            s.split(sep).collect()
+        }
+        Separator::AnyOf(chars) => s.split(|c| chars.contains(&c)).collect(),
+    };
+    if fields.len() != N {
+        return Err(ParseTupleError::WrongCount {
+            expected: N,
+            found: fields.len(),
+        });
+    }
+
+    let mut values = Vec::with_capacity(N);
+    for (index, field) in fields.into_iter().enumerate() {
+        let field = if trim { field.trim() } else { field };
+        match T::from_str(field) {
+            Ok(value) => values.push(value),
+            Err(_) => {
+                return Err(ParseTupleError::InvalidField {
+                    index,
+                    field: field.to_string(),
+                });
+            }
+        }
+    }
+    match values.try_into() {
+        Ok(values) => Ok(values),
+        Err(_) => unreachable!("field count was checked above"),
+    }
+}
+
+/// 把字符串解析为一个复数。支持以下几种写法，各部分之间可以有空白：
+///
+/// - 逗号分隔的实部和虚部：`1.25,-0.0625`、`(−0.75, 0.1)`
+/// - 代数形式，虚数单位写作`i`或`j`：`-0.743643+0.131825i`、`1e-3-2.5e-4j`、
+///   `2i`、`-i`、`0.5`
+/// - 极坐标形式`r@theta`，`theta`的单位是弧度：`1@3.14159`
+///
+/// 整个表达式可以用括号括起来，负号也可以写作 Unicode 的`−`（U+2212）。
+/// 如果不能正确解析，返回的错误会指出出错字符的位置。
+pub fn parse_complex(s: &str) -> Result<Complex<f64>, ParseComplexError> {
+    ComplexParser::new(s).parse()
+}
+
+/// 解析复数出错的原因。
+#[derive(Debug, Clone, PartialEq)]
+pub enum ComplexErrorKind {
+    /// 输入为空，或者只有空白。
+    Empty,
+    /// 出现了不应该出现的字符。
+    UnexpectedChar(char),
+    /// 输入在应该还有内容的地方结束了。
+    UnexpectedEnd,
+}
+
+/// `parse_complex`返回的错误。`position`是出错字符的位置，按字符（而不是字节）
+/// 从 0 开始计数。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseComplexError {
+    pub position: usize,
+    pub kind: ComplexErrorKind,
+}
+
+impl ParseComplexError {
+    /// 返回两行文本：原始输入，以及下一行指向出错字符的`^`。
+    pub fn pointer(&self, input: &str) -> String {
+        format!("{}\n{}^", input, " ".repeat(self.position))
+    }
+}
+
+impl fmt::Display for ParseComplexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ComplexErrorKind::Empty => write!(f, "empty complex number"),
+            ComplexErrorKind::UnexpectedChar(c) => {
+                write!(
+                    f,
+                    "unexpected character '{}' at position {}",
+                    c, self.position
+                )
+            }
+            ComplexErrorKind::UnexpectedEnd => {
+                write!(f, "unexpected end of input at position {}", self.position)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseComplexError {}
+
+/// `parse_complex`使用的手写递归下降解析器，逐个字符地读取输入。
+struct ComplexParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl ComplexParser {
+    fn new(s: &str) -> ComplexParser {
+        ComplexParser {
+            chars: s.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    fn parse(mut self) -> Result<Complex<f64>, ParseComplexError> {
+        self.skip_whitespace();
+        if self.peek().is_none() {
+            return Err(self.error(ComplexErrorKind::Empty));
+        }
+        let parenthesized = self.eat('(');
+        let value = self.body()?;
+        if parenthesized {
+            self.skip_whitespace();
+            if !self.eat(')') {
+                return Err(self.unexpected());
+            }
+        }
+        self.skip_whitespace();
+        if self.peek().is_some() {
+            return Err(self.unexpected());
+        }
+        Ok(value)
+    }
+
+    /// 括号里面的部分：`re,im`、`r@theta`、`a+bi`、`bi`或者`a`。
+    fn body(&mut self) -> Result<Complex<f64>, ParseComplexError> {
+        self.skip_whitespace();
+        let sign = self.sign().unwrap_or(1.0);
+        self.skip_whitespace();
+        if self.eat_imaginary_unit() {
+            return Ok(Complex { re: 0.0, im: sign });
+        }
+        let first = sign * self.number()?;
+
+        self.skip_whitespace();
+        if self.eat(',') {
+            let im = self.signed_number()?;
+            return Ok(Complex { re: first, im });
+        }
+        if self.eat('@') {
+            let theta = self.signed_number()?;
+            return Ok(Complex::from_polar(first, theta));
+        }
+        if self.eat_imaginary_unit() {
+            return Ok(Complex { re: 0.0, im: first });
+        }
+        if let Some(sign) = self.sign() {
+            self.skip_whitespace();
+            if self.eat_imaginary_unit() {
+                return Ok(Complex {
+                    re: first,
+                    im: sign,
+                });
+            }
+            let im = sign * self.number()?;
+            self.skip_whitespace();
+            if !self.eat_imaginary_unit() {
+                return Err(self.unexpected());
+            }
+            return Ok(Complex { re: first, im });
+        }
+        Ok(Complex { re: first, im: 0.0 })
+    }
+
+    fn signed_number(&mut self) -> Result<f64, ParseComplexError> {
+        self.skip_whitespace();
+        let sign = self.sign().unwrap_or(1.0);
+        self.skip_whitespace();
+        Ok(sign * self.number()?)
+    }
+
+    /// 不带符号的十进制数，例如`12`、`.5`、`2.5e-4`。
+    fn number(&mut self) -> Result<f64, ParseComplexError> {
+        let start = self.pos;
+        let mut text = String::new();
+        self.digits(&mut text);
+        if self.peek() == Some('.') {
+            text.push('.');
+            self.pos += 1;
+            self.digits(&mut text);
+        }
+        if !text.chars().any(|c| c.is_ascii_digit()) {
+            self.pos = start;
+            return Err(self.unexpected());
+        }
+
+        // 只有后面真的跟着指数时才把`e`当作指数的开始
+        if matches!(self.peek(), Some('e' | 'E')) {
+            let sign = match self.chars.get(self.pos + 1) {
+                Some('+') => Some('+'),
+                Some('-' | '\u{2212}') => Some('-'),
+                _ => None,
+            };
+            let digits_at = self.pos + 1 + sign.is_some() as usize;
+            if self
+                .chars
+                .get(digits_at)
+                .is_some_and(|c| c.is_ascii_digit())
+            {
+                text.push('e');
+                text.extend(sign);
+                self.pos = digits_at;
+                self.digits(&mut text);
+            }
+        }
+        Ok(f64::from_str(&text).expect("number text is always valid"))
+    }
+
+    fn digits(&mut self, text: &mut String) {
+        while let Some(c) = self.peek().filter(char::is_ascii_digit) {
+            text.push(c);
+            self.pos += 1;
+        }
+    }
+
+    /// 读取一个可选的正负号，返回`1.0`或`-1.0`。
+    fn sign(&mut self) -> Option<f64> {
+        let sign = match self.peek()? {
+            '+' => 1.0,
+            '-' | '\u{2212}' => -1.0,
+            _ => return None,
+        };
+        self.pos += 1;
+        Some(sign)
+    }
+
+    fn eat_imaginary_unit(&mut self) -> bool {
+        self.eat('i') || self.eat('j')
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn unexpected(&self) -> ParseComplexError {
+        match self.peek() {
+            Some(c) => self.error(ComplexErrorKind::UnexpectedChar(c)),
+            None => self.error(ComplexErrorKind::UnexpectedEnd),
+        }
+    }
+
+    fn error(&self, kind: ComplexErrorKind) -> ParseComplexError {
+        ParseComplexError {
+            position: self.pos,
+            kind,
+        }
     }
 }
 
@@ -50,9 +368,32 @@ pub fn pixel_to_point(
     }
 }
 
+/// `pixel_to_point`的逆运算：给定复平面上的点，返回它落在哪个像素里。
+///
+/// 参数的含义和`pixel_to_point`相同。如果`point`在图像覆盖的区域之外，
+/// 就返回`None`。
+pub fn point_to_pixel(
+    bounds: (usize, usize),
+    point: Complex<f64>,
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+) -> Option<(usize, usize)> {
+    let (width, height) = (
+        lower_right.re - upper_left.re,
+        upper_left.im - lower_right.im,
+    );
+    let column = (point.re - upper_left.re) / width * bounds.0 as f64;
+    let row = (upper_left.im - point.im) / height * bounds.1 as f64;
+    if column < 0.0 || row < 0.0 || column >= bounds.0 as f64 || row >= bounds.1 as f64 {
+        return None;
+    }
+    Some((column as usize, row as usize))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_parse_pair() {
@@ -65,23 +406,117 @@ mod tests {
         assert_eq!(parse_pair::<f64>("0.5x1.5", 'x'), Some((0.5, 1.5)));
     }
 
+    #[test]
+    fn test_parse_tuple() {
+        assert_eq!(
+            parse_tuple::<u8, 3>("255,128,0", ',', false),
+            Ok([255, 128, 0])
+        );
+        assert_eq!(parse_tuple::<usize, 2>("10 x 20", 'x', true), Ok([10, 20]));
+        assert_eq!(
+            parse_tuple::<usize, 2>("10 x 20", " x ", false),
+            Ok([10, 20])
+        );
+        assert_eq!(
+            parse_tuple::<f64, 3>("1.5; 2,3", &[',', ';'][..], true),
+            Ok([1.5, 2.0, 3.0])
+        );
+        assert_eq!(
+            parse_tuple::<usize, 2>("10 x 20", 'x', false),
+            Err(ParseTupleError::InvalidField {
+                index: 0,
+                field: "10 ".to_string()
+            })
+        );
+        assert_eq!(
+            parse_tuple::<u8, 3>("1,2", ',', false),
+            Err(ParseTupleError::WrongCount {
+                expected: 3,
+                found: 2
+            })
+        );
+        assert_eq!(
+            parse_tuple::<u8, 3>("1,2,300", ',', false)
+                .unwrap_err()
+                .to_string(),
+            "invalid field 3: \"300\""
+        );
+    }
+
     #[test]
     fn test_parse_complex() {
         assert_eq!(
             parse_complex("1.25,-0.0625"),
-            Some(Complex {
+            Ok(Complex {
                 re: 1.25,
                 im: -0.0625
             })
         );
         assert_eq!(
             parse_complex("1,-0.0625"),
-            Some(Complex {
+            Ok(Complex {
                 re: 1.0,
                 im: -0.0625
             })
         );
-        assert_eq!(parse_complex(",-0.0625"), None);
+        assert!(parse_complex(",-0.0625").is_err());
+    }
+
+    #[test]
+    fn test_parse_complex_syntax() {
+        let c = |re, im| Ok(Complex { re, im });
+        assert_eq!(parse_complex("-0.743643+0.131825i"), c(-0.743643, 0.131825));
+        assert_eq!(parse_complex("1e-3-2.5e-4j"), c(1e-3, -2.5e-4));
+        assert_eq!(parse_complex("(\u{2212}0.75, 0.1)"), c(-0.75, 0.1));
+        assert_eq!(parse_complex("  ( 1 - 2 i )  "), c(1.0, -2.0));
+        assert_eq!(parse_complex("2.5E+2"), c(250.0, 0.0));
+        assert_eq!(parse_complex(".5j"), c(0.0, 0.5));
+        assert_eq!(parse_complex("-i"), c(0.0, -1.0));
+        assert_eq!(parse_complex("3+i"), c(3.0, 1.0));
+        assert_eq!(parse_complex("2@0"), c(2.0, 0.0));
+
+        let polar = parse_complex("2 @ 1.5707963267948966").unwrap();
+        assert!(polar.re.abs() < 1e-12 && (polar.im - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_parse_complex_errors() {
+        let err = |position, kind| Err(ParseComplexError { position, kind });
+        assert_eq!(parse_complex("   "), err(3, ComplexErrorKind::Empty));
+        assert_eq!(
+            parse_complex("-1.2x0.35"),
+            err(4, ComplexErrorKind::UnexpectedChar('x'))
+        );
+        assert_eq!(
+            parse_complex("1+2"),
+            err(3, ComplexErrorKind::UnexpectedEnd)
+        );
+        assert_eq!(
+            parse_complex("(1,2"),
+            err(4, ComplexErrorKind::UnexpectedEnd)
+        );
+        // 位置按字符计数，`−`占三个字节但只算一个字符
+        assert_eq!(
+            parse_complex("\u{2212}1e"),
+            err(2, ComplexErrorKind::UnexpectedChar('e'))
+        );
+
+        let error = parse_complex("1,,2").unwrap_err();
+        assert_eq!(error.to_string(), "unexpected character ',' at position 2");
+        assert_eq!(error.pointer("1,,2"), "1,,2\n  ^");
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parse_complex_round_trips_display(
+            re in prop::num::f64::NORMAL | prop::num::f64::ZERO,
+            im in prop::num::f64::NORMAL | prop::num::f64::ZERO,
+        ) {
+            let c = Complex { re, im };
+            prop_assert_eq!(parse_complex(&c.to_string()), Ok(c));
+            prop_assert_eq!(parse_complex(&format!("{:e}", c)), Ok(c));
+            prop_assert_eq!(parse_complex(&format!("({}, {})", re, im)), Ok(c));
+        }
     }
 
     #[test]
@@ -99,4 +534,24 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_point_to_pixel() {
+        let upper_left = Complex { re: -1.0, im: 1.0 };
+        let lower_right = Complex { re: 1.0, im: -1.0 };
+        let point = pixel_to_point((100, 200), (25, 175), upper_left, lower_right);
+        assert_eq!(
+            point_to_pixel((100, 200), point, upper_left, lower_right),
+            Some((25, 175))
+        );
+        assert_eq!(
+            point_to_pixel(
+                (100, 200),
+                Complex { re: 1.5, im: 0.0 },
+                upper_left,
+                lower_right
+            ),
+            None
+        );
+    }
 }
@@ -0,0 +1,173 @@
+use crate::concurrency::palette::escape_color;
+use crate::concurrency::render_cache::RenderCache;
+use crate::concurrency::viewport::Viewport;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::{cursor, execute, queue, terminal};
+use std::io::{self, Write};
+
+/// 每次平移的距离，以视口的宽和高为单位。实际平移时取整到整数个像素，
+/// 这样可以重用上一帧的大部分结果。
+const PAN_STEP: f64 = 0.1;
+
+/// 每次缩放的倍数。
+const ZOOM_STEP: f64 = 1.5;
+
+/// 把`viewport`渲染成`size`（列，行）个终端字符单元。
+///
+/// 每个字符单元是一个上半块字符`▀`，前景色是上半个像素，背景色是
+/// 下半个像素，所以实际的分辨率是`size.0`×`2 * size.1`。颜色使用
+/// 24 位的 ANSI 转义序列，每一行以重置颜色结尾。
+pub fn preview_lines(viewport: Viewport, size: (usize, usize)) -> Vec<String> {
+    cache_lines(&RenderCache::new(viewport, preview_bounds(size), 255))
+}
+
+/// 终端里`size`个字符单元对应的像素尺寸。
+fn preview_bounds(size: (usize, usize)) -> (usize, usize) {
+    (size.0, size.1 * 2)
+}
+
+/// 和`preview_lines`相同，但使用已经算好的`cache`。
+fn cache_lines(cache: &RenderCache) -> Vec<String> {
+    let (width, height) = cache.bounds();
+    let color = |column, row| escape_color(cache.counts()[row * width + column], cache.limit());
+
+    (0..height / 2)
+        .map(|line| {
+            let mut text = String::new();
+            for column in 0..width {
+                let [r, g, b] = color(column, line * 2);
+                let [br, bg, bb] = color(column, line * 2 + 1);
+                text.push_str(&format!(
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                    r, g, b, br, bg, bb
+                ));
+            }
+            text.push_str("\x1b[0m");
+            text
+        })
+        .collect()
+}
+
+/// 根据按键更新视口。如果按键表示退出，就返回`None`。
+///
+/// 方向键或`h`/`j`/`k`/`l`平移，`+`/`=`放大，`-`缩小，
+/// `q`、`Esc`或回车退出，其它按键不改变视口。`bounds`是视口对应的像素尺寸，
+/// 平移的距离取整到整数个像素。
+pub fn apply_key(viewport: Viewport, bounds: (usize, usize), key: KeyCode) -> Option<Viewport> {
+    let columns = (bounds.0 as f64 * PAN_STEP).round().max(1.0) as isize;
+    let rows = (bounds.1 as f64 * PAN_STEP).round().max(1.0) as isize;
+    let viewport = match key {
+        KeyCode::Left | KeyCode::Char('h') => viewport.pan_pixels(bounds, -columns, 0),
+        KeyCode::Right | KeyCode::Char('l') => viewport.pan_pixels(bounds, columns, 0),
+        KeyCode::Up | KeyCode::Char('k') => viewport.pan_pixels(bounds, 0, -rows),
+        KeyCode::Down | KeyCode::Char('j') => viewport.pan_pixels(bounds, 0, rows),
+        KeyCode::Char('+') | KeyCode::Char('=') => viewport.zoom(ZOOM_STEP),
+        KeyCode::Char('-') => viewport.zoom(1.0 / ZOOM_STEP),
+        KeyCode::Char('q') | KeyCode::Esc | KeyCode::Enter => return None,
+        _ => viewport,
+    };
+    Some(viewport)
+}
+
+/// 在终端里交互式地浏览`viewport`，退出时返回最终的视口。
+///
+/// 使用终端的备用屏幕和原始模式，返回前会恢复终端。平移时只计算新露出来的
+/// 像素（参见`RenderCache`）。
+pub fn explore(mut viewport: Viewport) -> io::Result<Viewport> {
+    let mut stdout = io::stdout();
+    terminal::enable_raw_mode()?;
+    let _restore = RestoreTerminal;
+    execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+    let mut cache: Option<RenderCache> = None;
+    loop {
+        let (columns, rows) = terminal::size()?;
+        // 最后一行留给状态栏
+        let bounds = preview_bounds((columns as usize, rows.saturating_sub(1) as usize));
+        match &mut cache {
+            // 终端大小没变时重用上一帧
+            Some(cache) if cache.bounds() == bounds => {
+                cache.update(viewport);
+            }
+            _ => cache = Some(RenderCache::new(viewport, bounds, 255)),
+        }
+        let lines = cache_lines(cache.as_ref().unwrap());
+        for (i, line) in lines.iter().enumerate() {
+            queue!(stdout, cursor::MoveTo(0, i as u16))?;
+            stdout.write_all(line.as_bytes())?;
+        }
+        queue!(
+            stdout,
+            cursor::MoveTo(0, rows.saturating_sub(1)),
+            terminal::Clear(terminal::ClearType::CurrentLine)
+        )?;
+        write!(
+            stdout,
+            "{}  [arrows/hjkl] pan  [+/-] zoom  [q] done",
+            viewport
+        )?;
+        stdout.flush()?;
+
+        match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => {
+                match apply_key(viewport, bounds, key.code) {
+                    Some(next) => viewport = next,
+                    None => break,
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(viewport)
+}
+
+/// 在离开作用域时恢复终端，即使中途出错也不会把终端留在原始模式。
+struct RestoreTerminal;
+
+impl Drop for RestoreTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::Complex;
+
+    fn view() -> Viewport {
+        Viewport::new(Complex { re: -2.0, im: 1.2 }, Complex { re: 1.0, im: -1.2 })
+    }
+
+    #[test]
+    fn test_preview_lines() {
+        let lines = preview_lines(view(), (8, 3));
+        assert_eq!(lines.len(), 3);
+        for line in &lines {
+            assert_eq!(line.matches('\u{2580}').count(), 8);
+            assert!(line.ends_with("\x1b[0m"));
+        }
+        // 左上角离集合很远，很快逃逸，不是黑色
+        assert!(!lines[0].starts_with("\x1b[38;2;0;0;0m"));
+    }
+
+    #[test]
+    fn test_apply_key() {
+        let bounds = (80, 48);
+        assert_eq!(apply_key(view(), bounds, KeyCode::Char('q')), None);
+        assert_eq!(apply_key(view(), bounds, KeyCode::Char('x')), Some(view()));
+        assert_eq!(
+            apply_key(view(), bounds, KeyCode::Left),
+            Some(view().pan(-0.1, 0.0))
+        );
+        // 平移取整到整数个像素：48 行的十分之一取整为 5 行
+        let down = apply_key(view(), bounds, KeyCode::Down).unwrap();
+        assert_eq!(
+            RenderCache::new(view(), bounds, 1).pixel_shift(down),
+            Some((0, 5))
+        );
+        let zoomed = apply_key(view(), bounds, KeyCode::Char('+')).unwrap();
+        assert!(zoomed.width() < view().width());
+    }
+}
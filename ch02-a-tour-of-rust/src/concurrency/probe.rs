@@ -0,0 +1,247 @@
+use crate::concurrency::draw::{draw_polyline, render};
+use crate::concurrency::mandelbrot::{escape_time_orbit, smooth_escape_time};
+use crate::concurrency::nucleus::{attracting_period, estimate_period};
+use crate::concurrency::parse::point_to_pixel;
+use crate::concurrency::viewport::Viewport;
+use num::Complex;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// 一个点属于集合的哪一部分。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Classification {
+    /// 逃逸了，不在集合里。
+    Exterior,
+    /// 轨迹收敛到一个吸引周期轨道，在某个双曲分量内部。
+    Interior { period: usize },
+    /// 在迭代上限之内既没有逃逸，也没有收敛，多半离边界很近。
+    Undetermined,
+}
+
+impl Classification {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Classification::Exterior => "exterior",
+            Classification::Interior { .. } => "interior",
+            Classification::Undetermined => "undetermined",
+        }
+    }
+}
+
+/// 对单个点`c`做的检查，见`probe`。
+#[derive(Clone, Debug, PartialEq)]
+pub struct Probe {
+    pub c: Complex<f64>,
+    pub limit: usize,
+    /// 和`escape_time`的结果相同。
+    pub escape_time: Option<usize>,
+    /// `smooth_escape_time`的结果，只有逃逸的点才有。
+    pub smooth: Option<f64>,
+    /// `estimate_period`估计的周期：内部的点是吸引周期轨道的周期，
+    /// 外部的点是原子域的周期。
+    pub period: usize,
+    pub classification: Classification,
+    /// 轨迹`z_1`、`z_2`……，直到逃逸（包括逃逸时的那个点）或者迭代了`limit`次。
+    pub orbit: Vec<Complex<f64>>,
+}
+
+/// 迭代`c`最多`limit`次，收集解释这个点为什么是这种颜色所需的全部信息。
+pub fn probe(c: Complex<f64>, limit: usize) -> Probe {
+    let mut orbit = Vec::new();
+    let escape_time = escape_time_orbit(c, limit, |_, z| orbit.push(z));
+    let classification = match escape_time {
+        Some(_) => Classification::Exterior,
+        None => match attracting_period(c, limit) {
+            Some(period) => Classification::Interior { period },
+            None => Classification::Undetermined,
+        },
+    };
+    Probe {
+        c,
+        limit,
+        escape_time,
+        smooth: smooth_escape_time(c, limit),
+        period: estimate_period(c, limit),
+        classification,
+        orbit,
+    }
+}
+
+impl Probe {
+    /// 把轨迹写成 CSV，每行是迭代次数和`z`的实部、虚部。
+    pub fn write_csv<W: Write>(&self, mut output: W) -> io::Result<()> {
+        writeln!(output, "n,re,im")?;
+        for (i, z) in self.orbit.iter().enumerate() {
+            writeln!(output, "{},{},{}", i + 1, z.re, z.im)?;
+        }
+        Ok(())
+    }
+
+    /// 把整个检查结果写成 JSON，轨迹是`[re, im]`组成的数组。
+    pub fn to_json(&self) -> String {
+        // JSON 里没有 NaN 和无穷大，用 null 代替
+        let number = |x: f64| {
+            if x.is_finite() {
+                x.to_string()
+            } else {
+                "null".to_string()
+            }
+        };
+        let optional = |x: Option<String>| x.unwrap_or_else(|| "null".to_string());
+
+        let mut json = String::new();
+        write!(
+            json,
+            "{{\"c\":[{},{}],\"limit\":{},\"escape_time\":{},\"smooth\":{},\"period\":{},\"class\":\"{}\",\"orbit\":[",
+            number(self.c.re),
+            number(self.c.im),
+            self.limit,
+            optional(self.escape_time.map(|n| n.to_string())),
+            optional(self.smooth.map(number)),
+            self.period,
+            self.classification.name(),
+        )
+        .unwrap();
+        for (i, z) in self.orbit.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write!(json, "[{},{}]", number(z.re), number(z.im)).unwrap();
+        }
+        json.push_str("]}");
+        json
+    }
+
+    /// 一个能装下`c`和轨迹的视口（只算半径 2 以内的点），四周留一点空白，
+    /// 宽高比和`bounds`相同。
+    pub fn orbit_viewport(&self, bounds: (usize, usize)) -> Viewport {
+        let points = std::iter::once(self.c).chain(self.orbit.iter().copied());
+        let (mut low, mut high) = (self.c, self.c);
+        for z in points.filter(|z| z.norm_sqr() <= 4.0) {
+            low = Complex::new(low.re.min(z.re), low.im.min(z.im));
+            high = Complex::new(high.re.max(z.re), high.im.max(z.im));
+        }
+        let center = (low + high) / 2.0;
+        let aspect = bounds.0 as f64 / bounds.1 as f64;
+        // 至少 0.5 宽，避免轨迹停在一个点上时视口退化
+        let mut width = ((high.re - low.re) * 1.2).max(0.5);
+        let mut height = (high.im - low.im) * 1.2;
+        if width / height < aspect {
+            width = height * aspect;
+        } else {
+            height = width / aspect;
+        }
+        let half = Complex::new(width / 2.0, height / 2.0);
+        Viewport::new(center - half.conj(), center + half.conj())
+    }
+}
+
+/// 在`viewport`的灰度渲染上画出轨迹，结果写入 RGB 缓冲区`pixels`。
+///
+/// 轨迹相邻的点之间用黄线连起来，轨迹上的点是红色，`c`本身是青色。
+pub fn plot_orbit(pixels: &mut [u8], bounds: (usize, usize), viewport: Viewport, probe: &Probe) {
+    assert_eq!(pixels.len(), bounds.0 * bounds.1 * 3);
+
+    let mut gray = vec![0; bounds.0 * bounds.1];
+    render(&mut gray, bounds, viewport.upper_left, viewport.lower_right);
+    for (pixel, &value) in pixels.chunks_mut(3).zip(&gray) {
+        // 背景调暗一些，让轨迹更醒目
+        pixel.fill(value / 2);
+    }
+
+    let path: Vec<Complex<f64>> = std::iter::once(Complex::new(0.0, 0.0))
+        .chain(probe.orbit.iter().copied())
+        .collect();
+    let (upper_left, lower_right) = (viewport.upper_left, viewport.lower_right);
+    draw_polyline(
+        pixels,
+        bounds,
+        upper_left,
+        lower_right,
+        &path,
+        [255, 200, 0],
+    );
+    let mut put = |point: Complex<f64>, color: [u8; 3]| {
+        if let Some((column, row)) = point_to_pixel(bounds, point, upper_left, lower_right) {
+            let offset = (row * bounds.0 + column) * 3;
+            pixels[offset..offset + 3].copy_from_slice(&color);
+        }
+    };
+    for &z in &probe.orbit {
+        put(z, [255, 0, 0]);
+    }
+    put(probe.c, [0, 255, 255]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c(re: f64, im: f64) -> Complex<f64> {
+        Complex { re, im }
+    }
+
+    #[test]
+    fn test_probe_exterior() {
+        let result = probe(c(1.0, 0.0), 100);
+        assert_eq!(result.classification, Classification::Exterior);
+        // 轨迹是 1, 2, 5，在第三次迭代后逃逸
+        assert_eq!(result.escape_time, Some(3));
+        assert_eq!(result.orbit, vec![c(1.0, 0.0), c(2.0, 0.0), c(5.0, 0.0)]);
+        let smooth = result.smooth.unwrap();
+        assert!(smooth > 1.0 && smooth < 4.0, "{}", smooth);
+    }
+
+    #[test]
+    fn test_probe_interior() {
+        let result = probe(c(-1.0, 0.0), 100);
+        assert_eq!(
+            result.classification,
+            Classification::Interior { period: 2 }
+        );
+        assert_eq!(result.escape_time, None);
+        assert_eq!(result.smooth, None);
+        assert_eq!(result.period, 2);
+        assert_eq!(result.orbit.len(), 100);
+    }
+
+    #[test]
+    fn test_smooth_is_continuous() {
+        // 跨过逃逸时间的分界线时，平滑值的变化应该很小
+        let values: Vec<f64> = (0..50)
+            .map(|i| probe(c(0.3 + i as f64 * 1e-4, 0.0), 100).smooth.unwrap())
+            .collect();
+        assert!(values.windows(2).all(|w| (w[0] - w[1]).abs() < 0.1));
+    }
+
+    #[test]
+    fn test_write_csv_and_json() {
+        let result = probe(c(1.0, 0.0), 100);
+        let mut csv = Vec::new();
+        result.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "n,re,im\n1,1,0\n2,2,0\n3,5,0\n"
+        );
+
+        let json = result.to_json();
+        assert!(json.starts_with("{\"c\":[1,0],\"limit\":100,\"escape_time\":3,\"smooth\":"));
+        assert!(
+            json.ends_with("\"period\":1,\"class\":\"exterior\",\"orbit\":[[1,0],[2,0],[5,0]]}")
+        );
+        let interior = probe(c(0.0, 0.0), 2).to_json();
+        assert!(interior.contains("\"escape_time\":null,\"smooth\":null"));
+    }
+
+    #[test]
+    fn test_plot_orbit() {
+        let result = probe(c(-0.1, 0.65), 200);
+        let bounds = (60, 40);
+        let viewport = result.orbit_viewport(bounds);
+        assert!((viewport.width() / viewport.height() - 1.5).abs() < 1e-9);
+        let mut pixels = vec![0; bounds.0 * bounds.1 * 3];
+        plot_orbit(&mut pixels, bounds, viewport, &result);
+        assert!(pixels.chunks(3).any(|p| p == [0, 255, 255]));
+        assert!(pixels.chunks(3).any(|p| p == [255, 0, 0]));
+    }
+}
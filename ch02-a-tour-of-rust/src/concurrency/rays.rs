@@ -0,0 +1,340 @@
+use crate::concurrency::draw::draw_polyline;
+use num::Complex;
+use std::f64::consts::TAU;
+use std::fmt;
+use std::str::FromStr;
+
+/// 追踪从这个半径开始：`|z_n|`超出它之后，`z_n`的`2^(n-1)`次方根
+/// 就是很好的 Böttcher 坐标`φ(c)`的近似。
+const RAY_ESCAPE_RADIUS: f64 = 65536.0;
+
+/// 每一层（迭代次数加 1，`|φ|`开平方）分成这么多步，步数越多越不容易
+/// 跳到相邻的另一条射线上。
+const RAY_SHARPNESS: usize = 8;
+
+/// 牛顿法最多迭代的次数和相对步长的容差。
+const NEWTON_STEPS: usize = 64;
+const NEWTON_TOLERANCE: f64 = 1e-15;
+
+/// 追踪等势线时，`z_n`的角度每转一圈取这么多个点。
+const EQUIPOTENTIAL_SAMPLES_PER_TURN: usize = 64;
+
+/// 追踪等势线最多用到的层数。第`n`层的等势线要取`64 × 2^(n-1)`个点，
+/// 层数不加限制的话，半径接近 1 时点数和耗时都会无限增长。
+const EQUIPOTENTIAL_DEPTH: usize = 12;
+
+/// `equipotential`接受的最小半径，第`EQUIPOTENTIAL_DEPTH`层能到达的势
+/// `ln|φ| = ln(RAY_ESCAPE_RADIUS) / 2^12 ≈ 0.0027`再留一点余量。
+/// 这时最多取 131072 个点。
+pub const MIN_EQUIPOTENTIAL_RADIUS: f64 = 1.003;
+
+/// `Overlay::add_ray`追踪的层数，射线的终点离集合的势约为`1e-6`。
+pub const RAY_DEPTH: usize = 24;
+
+/// 外部射线的角度，以圈为单位的有理数`numerator / denominator`，
+/// 例如`1/3`、`2/7`。用整数保存，角度反复加倍时没有舍入误差。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Angle {
+    numerator: u64,
+    denominator: u64,
+}
+
+impl Angle {
+    /// `numerator / denominator`对 1 取模。
+    pub fn new(numerator: u64, denominator: u64) -> Angle {
+        assert!(denominator > 0, "angle denominator must not be zero");
+        Angle {
+            numerator: numerator % denominator,
+            denominator,
+        }
+    }
+
+    /// 以圈为单位的角度，在 0 到 1 之间。
+    pub fn turns(&self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+
+    /// 角度加倍再对 1 取模，对应`z ← z²`对 Böttcher 坐标的作用。
+    pub fn doubled(&self) -> Angle {
+        Angle::new(
+            ((self.numerator as u128 * 2) % self.denominator as u128) as u64,
+            self.denominator,
+        )
+    }
+}
+
+/// 形如`1/3`，或者一个整数（对 1 取模后是 0）。
+impl FromStr for Angle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Angle, String> {
+        let (numerator, denominator) = s.split_once('/').unwrap_or((s, "1"));
+        let parse = |field: &str| {
+            field
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| format!("invalid angle {:?}, expected `p/q`", s))
+        };
+        let (numerator, denominator) = (parse(numerator)?, parse(denominator)?);
+        if denominator == 0 {
+            return Err(format!("invalid angle {:?}: zero denominator", s));
+        }
+        Ok(Angle::new(numerator, denominator))
+    }
+}
+
+impl fmt::Display for Angle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+/// 从`c`出发用牛顿法解方程`z_n(c) = target`，`z_1 = c`。
+fn solve(mut c: Complex<f64>, n: usize, target: Complex<f64>) -> Complex<f64> {
+    for _ in 0..NEWTON_STEPS {
+        let mut z = Complex { re: 0.0, im: 0.0 };
+        let mut dc = Complex { re: 0.0, im: 0.0 };
+        for _ in 0..n {
+            dc = 2.0 * z * dc + 1.0;
+            z = z * z + c;
+        }
+        let step = (z - target) / dc;
+        if !step.is_finite() {
+            break;
+        }
+        c -= step;
+        if step.norm() <= NEWTON_TOLERANCE * c.norm().max(1.0) {
+            break;
+        }
+    }
+    c
+}
+
+/// 从`|φ| = RAY_ESCAPE_RADIUS`开始沿角度为`angle`的射线往里走，最多`depth`层，
+/// 在下一步的`|φ|`将小于`stop`时停下。返回走过的点和最后一个点所在的层。
+///
+/// 第`n`层的每一步都在上一个点附近解`z_n(c) = r e^(2πi 2^(n-1) angle)`，
+/// 其中`r`从`RAY_ESCAPE_RADIUS`逐步减小到它的平方根。
+fn trace(angle: Angle, depth: usize, stop: f64) -> (Vec<Complex<f64>>, usize) {
+    let mut c = Complex::from_polar(RAY_ESCAPE_RADIUS, TAU * angle.turns());
+    let mut points = vec![c];
+    // 第 n 层使用的角度是 2^(n-1) * angle
+    let mut doubled = angle;
+    for n in 1..=depth {
+        for j in 0..RAY_SHARPNESS {
+            let exponent = 0.5f64.powf((j + 1) as f64 / RAY_SHARPNESS as f64);
+            // 这一步的 |φ| 是 RAY_ESCAPE_RADIUS^(exponent / 2^(n-1))
+            let log_phi = RAY_ESCAPE_RADIUS.ln() * exponent / 2f64.powi(n as i32 - 1);
+            if log_phi < stop.ln() {
+                return (points, n);
+            }
+            let target =
+                Complex::from_polar(RAY_ESCAPE_RADIUS.powf(exponent), TAU * doubled.turns());
+            c = solve(c, n, target);
+            points.push(c);
+        }
+        doubled = doubled.doubled();
+    }
+    (points, depth)
+}
+
+/// 追踪角度为`angle`的外部射线：Böttcher 坐标`φ(c)`的辐角等于`angle`的曲线。
+///
+/// 返回从远处（`|c|`约为`RAY_ESCAPE_RADIUS`）走向集合的点，共`depth`层、
+/// 每层`RAY_SHARPNESS`步；最后一个点的`|φ|`是`RAY_ESCAPE_RADIUS^(2^-depth)`。
+/// 有理角度的射线落在集合的边界上，例如`1/3`和`2/3`落在 -0.75。
+pub fn external_ray(angle: Angle, depth: usize) -> Vec<Complex<f64>> {
+    trace(angle, depth, 1.0).0
+}
+
+/// 追踪`|φ(c)| = radius`的等势线，`radius`不能小于`MIN_EQUIPOTENTIAL_RADIUS`。
+///
+/// 先沿角度为 0 的射线走到这个势，再让目标的角度转一圈，每一步用牛顿法从
+/// 上一个点出发求解。返回的点首尾相接（最后一个点和第一个点几乎重合）。
+pub fn equipotential(radius: f64) -> Vec<Complex<f64>> {
+    assert!(
+        radius >= MIN_EQUIPOTENTIAL_RADIUS,
+        "equipotential radius must be at least {}",
+        MIN_EQUIPOTENTIAL_RADIUS
+    );
+    let (points, n) = trace(Angle::new(0, 1), EQUIPOTENTIAL_DEPTH, radius);
+    // 第 n 层里 z_n 和 φ 的关系是 z_n ≈ φ^(2^(n-1))
+    let power = 2f64.powi(n as i32 - 1);
+    let modulus = radius.powf(power);
+    let target = |turns: f64| Complex::from_polar(modulus, TAU * (power * turns).fract());
+
+    let mut c = solve(*points.last().unwrap(), n, target(0.0));
+    let mut curve = vec![c];
+    let samples = EQUIPOTENTIAL_SAMPLES_PER_TURN * power as usize;
+    for i in 1..=samples {
+        c = solve(c, n, target(i as f64 / samples as f64));
+        curve.push(c);
+    }
+    curve
+}
+
+/// 叠加在渲染结果上的线条。
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Overlay {
+    /// 每一项是一条折线和它的颜色。
+    pub lines: Vec<(Vec<Complex<f64>>, [u8; 3])>,
+}
+
+impl Overlay {
+    /// 加上一条角度为`angle`的外部射线，见`external_ray`。
+    pub fn add_ray(&mut self, angle: Angle, color: [u8; 3]) {
+        self.lines.push((external_ray(angle, RAY_DEPTH), color));
+    }
+
+    /// 加上一条`|φ| = radius`的等势线，见`equipotential`。
+    pub fn add_equipotential(&mut self, radius: f64, color: [u8; 3]) {
+        self.lines.push((equipotential(radius), color));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// 把`render`输出的灰度图像转成 RGB，再把所有线条画在上面。
+    /// 参数的含义和`render`相同。
+    pub fn composite(
+        &self,
+        gray: &[u8],
+        bounds: (usize, usize),
+        upper_left: Complex<f64>,
+        lower_right: Complex<f64>,
+    ) -> Vec<u8> {
+        assert_eq!(gray.len(), bounds.0 * bounds.1);
+        let mut pixels: Vec<u8> = gray.iter().flat_map(|&value| [value; 3]).collect();
+        for (points, color) in &self.lines {
+            draw_polyline(&mut pixels, bounds, upper_left, lower_right, points, *color);
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrency::draw::render;
+
+    fn angle(s: &str) -> Angle {
+        s.parse().unwrap()
+    }
+
+    /// 用足够多次迭代估计`ln|φ(c)|`，也就是 Green 函数的值。
+    fn potential(c: Complex<f64>) -> f64 {
+        let mut z = c;
+        let mut n = 1;
+        while z.norm() < 1e100 {
+            z = z * z + c;
+            n += 1;
+        }
+        z.norm().ln() / 2f64.powi(n - 1)
+    }
+
+    #[test]
+    fn test_angle() {
+        assert_eq!(angle("2/7").to_string(), "2/7");
+        assert_eq!(angle("2/7").doubled(), angle("4/7"));
+        assert_eq!(angle("4/7").doubled(), angle("1/7"));
+        assert_eq!(angle("5/4"), angle("1/4"));
+        assert_eq!(angle("0"), Angle::new(0, 1));
+        assert_eq!(angle("1/3").turns(), 1.0 / 3.0);
+        assert!("1/0".parse::<Angle>().is_err());
+        assert!("x".parse::<Angle>().is_err());
+    }
+
+    #[test]
+    fn test_external_ray() {
+        // 角度 1/2 的射线是实轴上 -2 左边的部分
+        let ray = external_ray(angle("1/2"), 20);
+        assert_eq!(ray.len(), 1 + 20 * RAY_SHARPNESS);
+        assert!(ray.iter().all(|c| c.im.abs() < 1e-9 && c.re < -2.0));
+        assert!(ray.windows(2).all(|w| w[1].re > w[0].re));
+        assert!(ray.last().unwrap().re > -2.01);
+
+        // 射线上每个点的 Böttcher 坐标的模都符合预期，误差来自用`z_n`的
+        // 方根近似`φ`
+        for (i, &c) in ray.iter().enumerate().skip(1) {
+            let (n, j) = ((i - 1) / RAY_SHARPNESS + 1, (i - 1) % RAY_SHARPNESS);
+            let exponent = 0.5f64.powf((j + 1) as f64 / RAY_SHARPNESS as f64);
+            let expected = RAY_ESCAPE_RADIUS.ln() * exponent / 2f64.powi(n as i32 - 1);
+            assert!((potential(c) / expected - 1.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_rays_land() {
+        // 1/6 的射线落在 Misiurewicz 点 i，收敛得很快
+        let sixth = external_ray(angle("1/6"), 30);
+        assert!((sixth.last().unwrap() - Complex::new(0.0, 1.0)).norm() < 1e-6);
+
+        // 1/3 和 2/3 的射线共轭，都落在周期 2 圆盘和主心形的切点 -0.75。
+        // 这是抛物点，射线靠近得很慢
+        let third = external_ray(angle("1/3"), 30);
+        let two_thirds = external_ray(angle("2/3"), 30);
+        for (a, b) in third.iter().zip(&two_thirds) {
+            assert!((a - b.conj()).norm() < 1e-9);
+        }
+        let end = *third.last().unwrap();
+        assert!((end - Complex::new(-0.75, 0.0)).norm() < 0.15, "{}", end);
+        assert!(end.im > 0.0);
+    }
+
+    #[test]
+    fn test_equipotential() {
+        for radius in [4.0, 1.5] {
+            let curve = equipotential(radius);
+            assert!((curve[0] - curve[curve.len() - 1]).norm() < 1e-6);
+            for &c in &curve {
+                assert!((potential(c) / radius.ln() - 1.0).abs() < 1e-3, "{}", c);
+            }
+            // 曲线绕原点转了一圈
+            let winding: f64 = curve.windows(2).map(|w| (w[1] / w[0]).arg()).sum::<f64>() / TAU;
+            assert!((winding - 1.0).abs() < 1e-6, "{}", winding);
+        }
+    }
+
+    #[test]
+    fn test_equipotential_limit() {
+        // 最小的半径用到最深的一层，点数有上限
+        let curve = equipotential(MIN_EQUIPOTENTIAL_RADIUS);
+        assert_eq!(
+            curve.len(),
+            1 + (EQUIPOTENTIAL_SAMPLES_PER_TURN << (EQUIPOTENTIAL_DEPTH - 1))
+        );
+        let radius = MIN_EQUIPOTENTIAL_RADIUS;
+        for &c in curve.iter().step_by(97) {
+            assert!((potential(c) / radius.ln() - 1.0).abs() < 1e-3, "{}", c);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "equipotential radius must be at least")]
+    fn test_equipotential_too_close() {
+        equipotential(1.0000001);
+    }
+
+    #[test]
+    fn test_composite() {
+        let bounds = (60, 40);
+        let upper_left = Complex { re: -2.2, im: 1.2 };
+        let lower_right = Complex { re: 0.8, im: -0.8 };
+        let mut gray = vec![0; bounds.0 * bounds.1];
+        render(&mut gray, bounds, upper_left, lower_right);
+
+        let mut overlay = Overlay::default();
+        assert!(overlay.is_empty());
+        assert_eq!(
+            overlay.composite(&gray, bounds, upper_left, lower_right),
+            gray.iter().flat_map(|&v| [v; 3]).collect::<Vec<u8>>()
+        );
+        overlay.add_ray(angle("2/7"), [255, 0, 0]);
+        overlay.add_equipotential(1.1, [0, 0, 255]);
+        let pixels = overlay.composite(&gray, bounds, upper_left, lower_right);
+        assert_eq!(pixels.len(), bounds.0 * bounds.1 * 3);
+        assert!(pixels.chunks(3).any(|p| p == [255, 0, 0]));
+        assert!(pixels.chunks(3).any(|p| p == [0, 0, 255]));
+    }
+}
@@ -0,0 +1,245 @@
+use crate::concurrency::mandelbrot::escape_time;
+use crate::concurrency::parse::pixel_to_point;
+use crate::concurrency::viewport::Viewport;
+
+/// 平移量离整数像素的差距小于它时，才认为是按整像素平移。
+const SHIFT_TOLERANCE: f64 = 1e-6;
+
+/// 缓存一个视口的逃逸时间，视口按整像素平移时只计算新露出来的部分。
+///
+/// 交互式浏览时每次只平移一小段，新视口和旧视口的大部分像素相同：
+/// `update`把重叠部分直接从旧结果里搬过来，只对新露出的条带调用`escape_time`。
+/// 缩放或者不按整像素平移时就只能全部重新计算。
+#[derive(Clone, Debug)]
+pub struct RenderCache {
+    viewport: Viewport,
+    bounds: (usize, usize),
+    limit: usize,
+    counts: Vec<Option<usize>>,
+}
+
+impl RenderCache {
+    /// 计算`viewport`里`bounds`个像素的逃逸时间，最多迭代`limit`次。
+    pub fn new(viewport: Viewport, bounds: (usize, usize), limit: usize) -> RenderCache {
+        let mut cache = RenderCache {
+            viewport,
+            bounds,
+            limit,
+            counts: vec![None; bounds.0 * bounds.1],
+        };
+        for row in 0..bounds.1 {
+            for column in 0..bounds.0 {
+                cache.counts[row * bounds.0 + column] = cache.compute(column, row);
+            }
+        }
+        cache
+    }
+
+    /// 和`new`相同，只是用`threads`个线程分行计算。每个像素仍然按它在整个视口里
+    /// 的位置计算，所以结果和`new`完全相同。
+    pub fn with_threads(
+        viewport: Viewport,
+        bounds: (usize, usize),
+        limit: usize,
+        threads: usize,
+    ) -> RenderCache {
+        let mut cache = RenderCache {
+            viewport,
+            bounds,
+            limit,
+            counts: Vec::new(),
+        };
+        let mut counts = vec![None; bounds.0 * bounds.1];
+        if !counts.is_empty() {
+            let chunk = bounds.1.div_ceil(threads.max(1)) * bounds.0;
+            let this = &cache;
+            crossbeam::scope(|spawner| {
+                for (i, rows) in counts.chunks_mut(chunk).enumerate() {
+                    spawner.spawn(move |_| {
+                        for (offset, count) in rows.iter_mut().enumerate() {
+                            let index = i * chunk + offset;
+                            *count = this.compute(index % bounds.0, index / bounds.0);
+                        }
+                    });
+                }
+            })
+            .expect("error joining threads");
+        }
+        cache.counts = counts;
+        cache
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        self.viewport
+    }
+
+    pub fn bounds(&self) -> (usize, usize) {
+        self.bounds
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// 每个像素的`escape_time`结果，按行排列。
+    pub fn counts(&self) -> &[Option<usize>] {
+        &self.counts
+    }
+
+    /// 如果`viewport`和当前视口大小相同、只是平移了整数个像素，就返回
+    /// 平移的（列，行）数：向右、向下为正。否则返回`None`。
+    pub fn pixel_shift(&self, viewport: Viewport) -> Option<(isize, isize)> {
+        let old = self.viewport;
+        let same_size = |a: f64, b: f64| (a - b).abs() <= a.abs() * 1e-9;
+        if !same_size(old.width(), viewport.width()) || !same_size(old.height(), viewport.height())
+        {
+            return None;
+        }
+        let pixel_width = old.width() / self.bounds.0 as f64;
+        let pixel_height = old.height() / self.bounds.1 as f64;
+        let columns = (viewport.upper_left.re - old.upper_left.re) / pixel_width;
+        let rows = (old.upper_left.im - viewport.upper_left.im) / pixel_height;
+        let whole = |x: f64| (x - x.round()).abs() < SHIFT_TOLERANCE && x.is_finite();
+        if !whole(columns) || !whole(rows) {
+            return None;
+        }
+        Some((columns.round() as isize, rows.round() as isize))
+    }
+
+    /// 把缓存更新到新的视口，返回实际计算了的像素数。
+    ///
+    /// 按整像素平移时只计算新露出来的像素，其余的从旧结果复制；
+    /// 否则重新计算所有像素。
+    pub fn update(&mut self, viewport: Viewport) -> usize {
+        let Some((dx, dy)) = self.pixel_shift(viewport) else {
+            *self = RenderCache::new(viewport, self.bounds, self.limit);
+            return self.counts.len();
+        };
+
+        let old = std::mem::take(&mut self.counts);
+        self.viewport = viewport;
+        self.counts = vec![None; old.len()];
+        let (width, height) = self.bounds;
+        let mut computed = 0;
+        for row in 0..height {
+            let old_row = row as isize + dy;
+            for column in 0..width {
+                let old_column = column as isize + dx;
+                let index = row * width + column;
+                if (0..width as isize).contains(&old_column)
+                    && (0..height as isize).contains(&old_row)
+                {
+                    self.counts[index] = old[old_row as usize * width + old_column as usize];
+                } else {
+                    self.counts[index] = self.compute(column, row);
+                    computed += 1;
+                }
+            }
+        }
+        computed
+    }
+
+    /// 按`render`的方式把缓存转换成灰度：集合内部是黑色，逃逸得越快越亮。
+    /// 迭代上限是 255 时结果和`render`完全相同。
+    pub fn write_grayscale(&self, pixels: &mut [u8]) {
+        assert_eq!(pixels.len(), self.counts.len());
+        for (pixel, count) in pixels.iter_mut().zip(&self.counts) {
+            *pixel = match *count {
+                None => 0,
+                Some(count) => 255 - (count * 255 / self.limit) as u8,
+            };
+        }
+    }
+
+    fn compute(&self, column: usize, row: usize) -> Option<usize> {
+        let point = pixel_to_point(
+            self.bounds,
+            (column, row),
+            self.viewport.upper_left,
+            self.viewport.lower_right,
+        );
+        escape_time(point, self.limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrency::draw::render;
+    use num::Complex;
+
+    // 像素大小是 2 的幂，平移前后的坐标都能精确表示，结果应该和直接渲染完全相同
+    const BOUNDS: (usize, usize) = (64, 48);
+
+    fn view() -> Viewport {
+        Viewport::new(Complex { re: -2.5, im: 1.5 }, Complex { re: 1.5, im: -1.5 })
+    }
+
+    fn rendered(viewport: Viewport) -> Vec<u8> {
+        let mut pixels = vec![0; BOUNDS.0 * BOUNDS.1];
+        render(
+            &mut pixels,
+            BOUNDS,
+            viewport.upper_left,
+            viewport.lower_right,
+        );
+        pixels
+    }
+
+    #[test]
+    fn test_pixel_shift() {
+        let cache = RenderCache::new(view(), BOUNDS, 255);
+        assert_eq!(cache.pixel_shift(view()), Some((0, 0)));
+        assert_eq!(cache.pixel_shift(view().pan(0.25, 0.0)), Some((16, 0)));
+        assert_eq!(cache.pixel_shift(view().pan(0.0, 0.25)), Some((0, -12)));
+        assert_eq!(cache.pixel_shift(view().pan(0.01, 0.0)), None);
+        assert_eq!(cache.pixel_shift(view().zoom(2.0)), None);
+    }
+
+    #[test]
+    fn test_with_threads() {
+        let cache = RenderCache::new(view(), BOUNDS, 255);
+        for threads in [1, 5, 100] {
+            let parallel = RenderCache::with_threads(view(), BOUNDS, 255, threads);
+            assert_eq!(parallel.counts(), cache.counts());
+        }
+        assert!(
+            RenderCache::with_threads(view(), (0, 4), 255, 2)
+                .counts()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_update_matches_render() {
+        let mut cache = RenderCache::new(view(), BOUNDS, 255);
+        let mut pixels = vec![0; BOUNDS.0 * BOUNDS.1];
+        cache.write_grayscale(&mut pixels);
+        assert_eq!(pixels, rendered(view()));
+
+        // 向右 4 列、向下 3 行
+        let moved = view().pan(0.0625, -0.0625);
+        assert_eq!(cache.update(moved), BOUNDS.0 * BOUNDS.1 - 60 * 45);
+        cache.write_grayscale(&mut pixels);
+        assert_eq!(pixels, rendered(moved));
+
+        // 向左、向上各移回来，也只计算新露出来的部分
+        assert_eq!(cache.update(view()), BOUNDS.0 * BOUNDS.1 - 60 * 45);
+        cache.write_grayscale(&mut pixels);
+        assert_eq!(pixels, rendered(view()));
+    }
+
+    #[test]
+    fn test_update_recomputes_everything() {
+        let mut cache = RenderCache::new(view(), BOUNDS, 255);
+        // 平移超过整个视口时没有重叠
+        assert_eq!(cache.update(view().pan(2.0, 0.0)), BOUNDS.0 * BOUNDS.1);
+        // 缩放之后必须全部重新计算
+        let zoomed = view().zoom(2.0);
+        assert_eq!(cache.update(zoomed), BOUNDS.0 * BOUNDS.1);
+        assert_eq!(cache.viewport(), zoomed);
+        let mut pixels = vec![0; BOUNDS.0 * BOUNDS.1];
+        cache.write_grayscale(&mut pixels);
+        assert_eq!(pixels, rendered(zoomed));
+    }
+}
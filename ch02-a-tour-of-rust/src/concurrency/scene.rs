@@ -0,0 +1,151 @@
+use crate::concurrency::formula::Formula;
+use crate::concurrency::parse::{parse_complex, parse_tuple};
+use crate::concurrency::trap::Trap;
+use crate::concurrency::viewport::Viewport;
+use std::fmt;
+use std::str::FromStr;
+
+/// 一个场景文件描述的渲染参数。
+///
+/// 场景文件每行一个`key = value`，`#`之后是注释，例如：
+///
+/// ```text
+/// # 海马谷
+/// pixels = 1000x750
+/// upper_left = -1.20,0.35
+/// lower_right = -1,0.20
+/// trap = circle:0,0;0.5
+/// formula = z^2 + c
+/// ```
+///
+/// `pixels`、`upper_left`和`lower_right`是必需的，`trap`和`formula`是可选的。
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scene {
+    pub bounds: (usize, usize),
+    pub viewport: Viewport,
+    pub trap: Option<Trap>,
+    pub formula: Option<Formula>,
+}
+
+impl FromStr for Scene {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Scene, String> {
+        let mut bounds = None;
+        let mut upper_left = None;
+        let mut lower_right = None;
+        let mut trap = None;
+        let mut formula = None;
+
+        for (number, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected `key = value`, found {:?}", line)))?;
+            let value = value.trim();
+            match key.trim() {
+                "pixels" => {
+                    let [width, height] =
+                        parse_tuple(value, 'x', true).map_err(|err| error(err.to_string()))?;
+                    bounds = Some((width, height));
+                }
+                "upper_left" => {
+                    upper_left = Some(parse_complex(value).map_err(|err| error(err.to_string()))?)
+                }
+                "lower_right" => {
+                    lower_right = Some(parse_complex(value).map_err(|err| error(err.to_string()))?)
+                }
+                "trap" => trap = Some(value.parse().map_err(error)?),
+                "formula" => {
+                    formula = Some(
+                        value
+                            .parse::<Formula>()
+                            .map_err(|err| error(err.to_string()))?,
+                    )
+                }
+                other => return Err(error(format!("unknown key {:?}", other))),
+            }
+        }
+
+        let missing = |key: &str| format!("missing `{}`", key);
+        Ok(Scene {
+            bounds: bounds.ok_or_else(|| missing("pixels"))?,
+            viewport: Viewport::new(
+                upper_left.ok_or_else(|| missing("upper_left"))?,
+                lower_right.ok_or_else(|| missing("lower_right"))?,
+            ),
+            trap,
+            formula,
+        })
+    }
+}
+
+/// 输出`from_str`能解析的场景文件。
+impl fmt::Display for Scene {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Viewport {
+            upper_left,
+            lower_right,
+        } = self.viewport;
+        writeln!(f, "pixels = {}x{}", self.bounds.0, self.bounds.1)?;
+        writeln!(f, "upper_left = {},{}", upper_left.re, upper_left.im)?;
+        writeln!(f, "lower_right = {},{}", lower_right.re, lower_right.im)?;
+        if let Some(trap) = &self.trap {
+            writeln!(f, "trap = {}", trap)?;
+        }
+        if let Some(formula) = &self.formula {
+            writeln!(f, "formula = {}", formula)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::Complex;
+
+    #[test]
+    fn test_parse_scene() {
+        let scene: Scene = "
+            # 海马谷
+            pixels = 1000 x 750
+            upper_left = -1.20,0.35   # 左上角
+            lower_right = -1+0.2i
+            trap = circle:0,0;0.5
+            formula = conj(z)^2 + c
+        "
+        .parse()
+        .unwrap();
+        assert_eq!(scene.bounds, (1000, 750));
+        assert_eq!(scene.viewport.upper_left, Complex { re: -1.2, im: 0.35 });
+        assert_eq!(scene.viewport.lower_right, Complex { re: -1.0, im: 0.2 });
+        assert_eq!(
+            scene.trap,
+            Some(Trap::Circle {
+                center: Complex { re: 0.0, im: 0.0 },
+                radius: 0.5
+            })
+        );
+        assert_eq!(scene.formula, Some("conj(z)^2 + c".parse().unwrap()));
+        assert_eq!(scene.to_string().parse(), Ok(scene));
+    }
+
+    #[test]
+    fn test_parse_scene_errors() {
+        assert_eq!(
+            "pixels = 10x10\ncolour = red".parse::<Scene>(),
+            Err("line 2: unknown key \"colour\"".to_string())
+        );
+        assert_eq!(
+            "pixels = 10x10\nupper_left = 0,0".parse::<Scene>(),
+            Err("missing `lower_right`".to_string())
+        );
+        assert!("pixels 10x10".parse::<Scene>().is_err());
+        assert!("pixels = 10x".parse::<Scene>().is_err());
+    }
+}
@@ -0,0 +1,425 @@
+use crate::concurrency::draw::{Band, render_bands};
+use crate::concurrency::mandelbrot::escape_time;
+use num::Complex;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// `render`使用的迭代上限。
+const LIMIT: usize = 255;
+
+/// 表格里直方图最多分成这么多段。
+const HISTOGRAM_ROWS: usize = 16;
+
+/// 一次渲染的统计信息。
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    pub limit: usize,
+    /// `histogram[n]`是迭代`n`次后逃逸的像素数。
+    pub histogram: Vec<u64>,
+    /// 迭代了`limit`次仍未逃逸（画成黑色）的像素数。
+    pub inside: u64,
+    /// 每个条带的统计，按从上到下的顺序。条带的划分见`draw::BANDS`，
+    /// 和线程数无关。
+    pub bands: Vec<BandStats>,
+    /// 每个线程的统计，按线程的编号排列。
+    pub threads: Vec<ThreadStats>,
+    /// 整个渲染花的时间。
+    pub elapsed: Duration,
+}
+
+/// 一个条带的统计信息。
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BandStats {
+    /// 条带的第一行。
+    pub top: usize,
+    pub rows: usize,
+    pub elapsed: Duration,
+    /// 条带里所有像素迭代次数的总和，和机器无关的工作量。
+    pub iterations: u64,
+    /// 渲染这个条带的线程的编号。
+    pub thread: usize,
+}
+
+/// 一个渲染线程的统计信息。
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ThreadStats {
+    /// 这个线程渲染了几个条带。
+    pub bands: usize,
+    /// 渲染这些条带花的时间之和。
+    pub busy: Duration,
+}
+
+impl RenderStats {
+    pub fn new(limit: usize) -> RenderStats {
+        RenderStats {
+            limit,
+            histogram: vec![0; limit],
+            ..RenderStats::default()
+        }
+    }
+
+    /// 记录一个像素的`escape_time`结果。
+    pub fn record(&mut self, count: Option<usize>) {
+        match count {
+            Some(n) => self.histogram[n] += 1,
+            None => self.inside += 1,
+        }
+    }
+
+    /// 把另一次（例如另一个条带的）统计合并进来。
+    pub fn merge(&mut self, other: &RenderStats) {
+        for (total, count) in self.histogram.iter_mut().zip(&other.histogram) {
+            *total += count;
+        }
+        self.inside += other.inside;
+        self.bands.extend_from_slice(&other.bands);
+    }
+
+    pub fn pixels(&self) -> u64 {
+        self.histogram.iter().sum::<u64>() + self.inside
+    }
+
+    /// 集合内部（没有逃逸）的像素所占的比例。
+    pub fn inside_fraction(&self) -> f64 {
+        self.inside as f64 / self.pixels().max(1) as f64
+    }
+
+    /// 逃逸的像素里最多的迭代次数。离`limit`越近，越可能有本该逃逸的点被
+    /// 画成了黑色，需要加大`limit`。
+    pub fn max_escape(&self) -> Option<usize> {
+        self.histogram.iter().rposition(|&count| count > 0)
+    }
+
+    /// 逃逸的像素的平均迭代次数。
+    pub fn mean_escape(&self) -> Option<f64> {
+        let escaped: u64 = self.histogram.iter().sum();
+        let total: u64 = self
+            .histogram
+            .iter()
+            .enumerate()
+            .map(|(n, &count)| n as u64 * count)
+            .sum();
+        (escaped > 0).then(|| total as f64 / escaped as f64)
+    }
+
+    /// 所有像素的迭代次数之和，没有逃逸的像素按`limit`次计算。
+    pub fn iterations(&self) -> u64 {
+        let escaped: u64 = self
+            .histogram
+            .iter()
+            .enumerate()
+            .map(|(n, &count)| n as u64 * count)
+            .sum();
+        escaped + self.inside * self.limit as u64
+    }
+
+    /// 输出适合在终端里阅读的表格。
+    pub fn to_table(&self) -> String {
+        let mut table = String::new();
+        let optional = |x: Option<String>| x.unwrap_or_else(|| "-".to_string());
+        writeln!(table, "pixels        {}", self.pixels()).unwrap();
+        writeln!(
+            table,
+            "inside        {} ({:.2}%)",
+            self.inside,
+            self.inside_fraction() * 100.0
+        )
+        .unwrap();
+        writeln!(
+            table,
+            "max escape    {} (limit {})",
+            optional(self.max_escape().map(|n| n.to_string())),
+            self.limit
+        )
+        .unwrap();
+        writeln!(
+            table,
+            "mean escape   {}",
+            optional(self.mean_escape().map(|m| format!("{:.2}", m)))
+        )
+        .unwrap();
+        writeln!(table, "iterations    {}", self.iterations()).unwrap();
+        writeln!(table, "elapsed       {:.3} ms", millis(self.elapsed)).unwrap();
+
+        if !self.bands.is_empty() {
+            writeln!(table).unwrap();
+            writeln!(table, "band  rows         time (ms)   iterations  thread").unwrap();
+            for (i, band) in self.bands.iter().enumerate() {
+                writeln!(
+                    table,
+                    "{:>4}  {:<11}  {:>9.3}  {:>11}  {:>6}",
+                    i,
+                    format!("{}-{}", band.top, band.top + band.rows),
+                    millis(band.elapsed),
+                    band.iterations,
+                    band.thread
+                )
+                .unwrap();
+            }
+        }
+
+        if !self.threads.is_empty() {
+            writeln!(table).unwrap();
+            writeln!(table, "thread  bands  busy (ms)  load").unwrap();
+            for (i, thread) in self.threads.iter().enumerate() {
+                writeln!(
+                    table,
+                    "{:>6}  {:>5}  {:>9.3}  {:>4.0}%",
+                    i,
+                    thread.bands,
+                    millis(thread.busy),
+                    self.load(thread) * 100.0
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(table).unwrap();
+        writeln!(table, "escape count  pixels").unwrap();
+        let bucket = self.limit.div_ceil(HISTOGRAM_ROWS).max(1);
+        let largest = self
+            .histogram
+            .chunks(bucket)
+            .map(|chunk| chunk.iter().sum::<u64>())
+            .max()
+            .unwrap_or(0)
+            .max(self.inside);
+        let bar = |count: u64| "#".repeat((count * 40).div_ceil(largest.max(1)) as usize);
+        for (i, chunk) in self.histogram.chunks(bucket).enumerate() {
+            let count: u64 = chunk.iter().sum();
+            let range = format!("{}-{}", i * bucket, i * bucket + chunk.len() - 1);
+            writeln!(table, "{:<12}  {:>8}  {}", range, count, bar(count)).unwrap();
+        }
+        writeln!(
+            table,
+            "{:<12}  {:>8}  {}",
+            "inside",
+            self.inside,
+            bar(self.inside)
+        )
+        .unwrap();
+        table
+    }
+
+    /// 输出 JSON，直方图是完整的数组，时间以毫秒为单位。
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        write!(
+            json,
+            "{{\"pixels\":{},\"inside\":{},\"inside_fraction\":{},\"limit\":{},\"max_escape\":{},\"mean_escape\":{},\"iterations\":{},\"elapsed_ms\":{},\"histogram\":{:?},\"bands\":[",
+            self.pixels(),
+            self.inside,
+            self.inside_fraction(),
+            self.limit,
+            self.max_escape().map_or("null".to_string(), |n| n.to_string()),
+            self.mean_escape().map_or("null".to_string(), |m| m.to_string()),
+            self.iterations(),
+            millis(self.elapsed),
+            self.histogram,
+        )
+        .unwrap();
+        for (i, band) in self.bands.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write!(
+                json,
+                "{{\"top\":{},\"rows\":{},\"elapsed_ms\":{},\"iterations\":{},\"thread\":{}}}",
+                band.top,
+                band.rows,
+                millis(band.elapsed),
+                band.iterations,
+                band.thread
+            )
+            .unwrap();
+        }
+        json.push_str("],\"threads\":[");
+        for (i, thread) in self.threads.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write!(
+                json,
+                "{{\"bands\":{},\"busy_ms\":{},\"load\":{}}}",
+                thread.bands,
+                millis(thread.busy),
+                self.load(thread)
+            )
+            .unwrap();
+        }
+        json.push_str("]}");
+        json
+    }
+
+    /// 线程在整个渲染时间里忙碌的比例。
+    fn load(&self, thread: &ThreadStats) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        thread.busy.as_secs_f64() / self.elapsed.as_secs_f64()
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// 和`render_band`相同，同时收集统计信息。
+pub fn render_with_stats(pixels: &mut [u8], band: Band) -> RenderStats {
+    let bounds = band.size();
+    assert_eq!(pixels.len(), bounds.0 * bounds.1);
+
+    let start = Instant::now();
+    let mut stats = RenderStats::new(LIMIT);
+    for row in 0..bounds.1 {
+        for column in 0..bounds.0 {
+            let count = escape_time(band.point(column, row), LIMIT);
+            stats.record(count);
+            pixels[row * bounds.0 + column] = match count {
+                None => 0,
+                Some(count) => 255 - count as u8,
+            };
+        }
+    }
+    stats.elapsed = start.elapsed();
+    stats
+}
+
+/// 和用`render_band`调用`render_bands`相同，同时收集整个图像、每个条带和
+/// 每个线程的统计信息。
+pub fn render_bands_with_stats(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    threads: usize,
+) -> RenderStats {
+    let start = Instant::now();
+    let bands = Mutex::new(Vec::new());
+    render_bands(
+        pixels,
+        bounds,
+        upper_left,
+        lower_right,
+        threads,
+        |pixels, band| {
+            let stats = render_with_stats(pixels, band);
+            bands.lock().unwrap().push((band, stats));
+        },
+    );
+
+    let mut bands = bands.into_inner().unwrap();
+    bands.sort_by_key(|(band, _)| band.top);
+    let mut total = RenderStats::new(LIMIT);
+    // 和`render_bands`一样，线程数不超过条带数；没有领到条带的线程也占一行
+    let workers = if bands.is_empty() {
+        0
+    } else {
+        threads.clamp(1, bands.len())
+    };
+    total.threads = vec![ThreadStats::default(); workers];
+    for (band, stats) in &bands {
+        total.merge(stats);
+        total.bands.push(BandStats {
+            top: band.top,
+            rows: band.rows,
+            elapsed: stats.elapsed,
+            iterations: stats.iterations(),
+            thread: band.worker,
+        });
+        let thread = &mut total.threads[band.worker];
+        thread.bands += 1;
+        thread.busy += stats.elapsed;
+    }
+    total.elapsed = start.elapsed();
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrency::draw::render;
+
+    const UPPER_LEFT: Complex<f64> = Complex { re: -2.0, im: 1.2 };
+    const LOWER_RIGHT: Complex<f64> = Complex { re: 1.0, im: -1.2 };
+
+    #[test]
+    fn test_render_with_stats_matches_render() {
+        let bounds = (30, 20);
+        let mut expected = vec![0; bounds.0 * bounds.1];
+        render(&mut expected, bounds, UPPER_LEFT, LOWER_RIGHT);
+        let mut pixels = vec![0; bounds.0 * bounds.1];
+        let stats = render_with_stats(&mut pixels, Band::whole(bounds, UPPER_LEFT, LOWER_RIGHT));
+        assert_eq!(pixels, expected);
+
+        assert_eq!(stats.pixels(), 600);
+        let black = expected.iter().filter(|&&p| p == 0).count() as u64;
+        assert_eq!(stats.inside, black);
+        assert!(stats.inside_fraction() > 0.1 && stats.inside_fraction() < 0.5);
+        assert!(stats.max_escape().unwrap() < 255);
+        assert!(stats.mean_escape().unwrap() >= 1.0);
+    }
+
+    #[test]
+    fn test_render_bands_with_stats() {
+        let bounds = (30, 20);
+        let mut single = vec![0; bounds.0 * bounds.1];
+        let whole = render_with_stats(&mut single, Band::whole(bounds, UPPER_LEFT, LOWER_RIGHT));
+        let mut pixels = vec![0; bounds.0 * bounds.1];
+        let stats = render_bands_with_stats(&mut pixels, bounds, UPPER_LEFT, LOWER_RIGHT, 3);
+        assert_eq!(pixels, single);
+        assert_eq!(stats.histogram, whole.histogram);
+        assert_eq!(stats.inside, whole.inside);
+
+        // 行数比`BANDS`少时每行一个条带
+        let bands: Vec<(usize, usize)> = stats.bands.iter().map(|b| (b.top, b.rows)).collect();
+        assert_eq!(bands, (0..20).map(|top| (top, 1)).collect::<Vec<_>>());
+        // 条带的划分和线程数无关
+        let mut other = vec![0; bounds.0 * bounds.1];
+        let eight = render_bands_with_stats(&mut other, bounds, UPPER_LEFT, LOWER_RIGHT, 8);
+        assert_eq!(other, pixels);
+        let layout: Vec<(usize, usize)> = eight.bands.iter().map(|b| (b.top, b.rows)).collect();
+        assert_eq!(layout, bands);
+        let work: u64 = stats.bands.iter().map(|b| b.iterations).sum();
+        assert_eq!(work, stats.iterations());
+
+        // 每个线程一行，忙碌的时间正好是它渲染的条带的时间之和
+        assert_eq!(stats.threads.len(), 3);
+        for (i, thread) in stats.threads.iter().enumerate() {
+            let mine: Vec<&BandStats> = stats.bands.iter().filter(|b| b.thread == i).collect();
+            assert_eq!(thread.bands, mine.len());
+            assert_eq!(thread.busy, mine.iter().map(|b| b.elapsed).sum());
+        }
+        let busy: Duration = stats.threads.iter().map(|t| t.busy).sum();
+        assert_eq!(busy, stats.bands.iter().map(|b| b.elapsed).sum());
+        assert_eq!(eight.threads.len(), 8);
+        let table = stats.to_table();
+        assert!(table.contains("thread  bands  busy (ms)  load"));
+        assert!(stats.to_json().contains("\"threads\":[{\"bands\":"));
+    }
+
+    #[test]
+    fn test_stats_summary() {
+        let mut stats = RenderStats::new(10);
+        stats.record(Some(2));
+        stats.record(Some(4));
+        stats.record(None);
+        stats.record(None);
+        assert_eq!(stats.pixels(), 4);
+        assert_eq!(stats.inside_fraction(), 0.5);
+        assert_eq!(stats.max_escape(), Some(4));
+        assert_eq!(stats.mean_escape(), Some(3.0));
+        assert_eq!(stats.iterations(), 26);
+        assert_eq!(RenderStats::new(10).mean_escape(), None);
+
+        let table = stats.to_table();
+        assert!(table.contains("inside        2 (50.00%)"));
+        let json = stats.to_json();
+        assert!(
+            json.starts_with("{\"pixels\":4,\"inside\":2,\"inside_fraction\":0.5,\"limit\":10,")
+        );
+        assert!(json.contains("\"histogram\":[0, 0, 1, 0, 1, 0, 0, 0, 0, 0]"));
+        assert!(json.ends_with("\"bands\":[],\"threads\":[]}"));
+    }
+}
@@ -0,0 +1,226 @@
+use core_affinity::CoreId;
+use std::fmt;
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::thread;
+
+/// 覆盖线程数的环境变量，优先级低于命令行上的`--threads`。
+pub const THREADS_ENV: &str = "MANDELBROT_THREADS";
+
+/// 线程数是从哪里来的。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// 命令行上的`--threads`。
+    Flag,
+    /// 环境变量`THREADS_ENV`。
+    Environment,
+    /// cgroup 的 CPU 配额，例如容器的`--cpus`。
+    CpuQuota,
+    /// 标准库报告的可用并行度：核心数，以及进程的 CPU 亲和性。
+    Available,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Flag => write!(f, "--threads"),
+            Source::Environment => write!(f, "{}", THREADS_ENV),
+            Source::CpuQuota => write!(f, "cgroup CPU quota"),
+            Source::Available => write!(f, "available parallelism"),
+        }
+    }
+}
+
+/// 选定的线程数和它的来源。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThreadCount {
+    pub threads: usize,
+    pub source: Source,
+}
+
+impl ThreadCount {
+    /// 依次使用命令行参数`flag`、环境变量的值`env`，都没有时自动检测。
+    pub fn resolve(flag: Option<&str>, env: Option<&str>) -> Result<ThreadCount, String> {
+        let parse = |value: &str, source: Source| match value.trim().parse::<usize>() {
+            Ok(threads) if threads > 0 => Ok(ThreadCount { threads, source }),
+            _ => Err(format!(
+                "invalid thread count {:?} from {}, expected a positive integer",
+                value, source
+            )),
+        };
+        match (flag, env) {
+            (Some(flag), _) => parse(flag, Source::Flag),
+            (None, Some(env)) if !env.trim().is_empty() => parse(env, Source::Environment),
+            _ => Ok(detect()),
+        }
+    }
+}
+
+impl fmt::Display for ThreadCount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let plural = if self.threads == 1 { "" } else { "s" };
+        write!(f, "{} thread{} ({})", self.threads, plural, self.source)
+    }
+}
+
+/// 检测这个进程实际能用上的线程数。
+///
+/// 标准库的`available_parallelism`已经考虑了 CPU 亲和性，在 Linux 上也会读
+/// cgroup 配额；这里再读一次配额，是为了在报告里说明线程数是被配额限制的。
+/// 64 核的机器上只分到 4 个 CPU 的容器开 64 个线程只会互相抢时间片。
+pub fn detect() -> ThreadCount {
+    let available = thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1);
+    match cgroup_cpu_limit() {
+        Some(limit) if limit <= available => ThreadCount {
+            threads: limit,
+            source: Source::CpuQuota,
+        },
+        _ => ThreadCount {
+            threads: available,
+            source: Source::Available,
+        },
+    }
+}
+
+/// cgroup 的 CPU 配额允许同时运行的线程数，没有配额时返回`None`。
+///
+/// 配额对整棵子树生效，所以要检查进程所在的 cgroup 和它的所有上级，
+/// 取最小的一个。同时支持 cgroup v2（`cpu.max`）和 v1（`cpu.cfs_quota_us`）。
+fn cgroup_cpu_limit() -> Option<usize> {
+    let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let mut limit: Option<usize> = None;
+    for line in cgroups.lines() {
+        // 每行形如`层级:控制器列表:路径`，v2 的控制器列表是空的
+        let mut fields = line.splitn(3, ':');
+        let (Some(_), Some(controllers), Some(path)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let read = |root: &str, dir: &Path, file: &str| {
+            let dir = dir.strip_prefix("/").unwrap_or(dir);
+            fs::read_to_string(Path::new(root).join(dir).join(file)).ok()
+        };
+        let quotas = Path::new(path).ancestors().filter_map(|dir| {
+            if controllers.is_empty() {
+                parse_cpu_max(&read("/sys/fs/cgroup", dir, "cpu.max")?)
+            } else if controllers.split(',').any(|c| c == "cpu") {
+                parse_cfs_quota(
+                    &read("/sys/fs/cgroup/cpu", dir, "cpu.cfs_quota_us")?,
+                    &read("/sys/fs/cgroup/cpu", dir, "cpu.cfs_period_us")?,
+                )
+            } else {
+                None
+            }
+        });
+        for quota in quotas {
+            limit = Some(limit.map_or(quota, |limit| limit.min(quota)));
+        }
+    }
+    limit
+}
+
+/// 解析 cgroup v2 的`cpu.max`，形如`150000 100000`（每个周期可用的微秒数和周期）
+/// 或者`max 100000`（没有配额）。
+pub fn parse_cpu_max(s: &str) -> Option<usize> {
+    let mut words = s.split_whitespace();
+    let quota = words.next()?.parse().ok()?;
+    let period = words
+        .next()
+        .map_or(Some(100_000), |period| period.parse().ok())?;
+    quota_threads(quota, period)
+}
+
+/// 解析 cgroup v1 的`cpu.cfs_quota_us`和`cpu.cfs_period_us`，配额为 -1 表示没有配额。
+pub fn parse_cfs_quota(quota: &str, period: &str) -> Option<usize> {
+    quota_threads(quota.trim().parse().ok()?, period.trim().parse().ok()?)
+}
+
+/// 配额能完整用满的线程数，至少为 1。1.5 个 CPU 的配额只开一个线程，
+/// 多开的线程只会在周期末尾被一起暂停。
+fn quota_threads(quota: u64, period: u64) -> Option<usize> {
+    if period == 0 {
+        return None;
+    }
+    Some(((quota / period) as usize).max(1))
+}
+
+/// `pin_bands`之后渲染线程依次固定到的核心。
+static BAND_CORES: OnceLock<Vec<CoreId>> = OnceLock::new();
+
+/// 让之后的`render_bands`把第`i`个渲染线程固定在第`i % n`个核心上，
+/// `n`是这个进程可以使用的核心数。返回`n`；平台不支持时返回 0，
+/// 线程照常由系统调度。
+///
+/// 固定之后线程不会在核心之间迁移，缓存一直是热的，各个条带的耗时也更稳定；
+/// 但和别的进程共用机器时，被占用的核心上的线程会慢下来。
+pub fn pin_bands() -> usize {
+    BAND_CORES
+        .get_or_init(|| core_affinity::get_core_ids().unwrap_or_default())
+        .len()
+}
+
+/// 如果调用过`pin_bands`，就把当前线程固定到第`worker`个渲染线程对应的核心上。
+pub(crate) fn pin_worker(worker: usize) {
+    if let Some(cores) = BAND_CORES.get()
+        && !cores.is_empty()
+    {
+        core_affinity::set_for_current(cores[worker % cores.len()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_max() {
+        assert_eq!(parse_cpu_max("400000 100000\n"), Some(4));
+        assert_eq!(parse_cpu_max("150000 100000"), Some(1));
+        assert_eq!(parse_cpu_max("50000 100000"), Some(1));
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+        assert_eq!(parse_cpu_max("200000"), Some(2));
+        assert_eq!(parse_cpu_max(""), None);
+    }
+
+    #[test]
+    fn test_parse_cfs_quota() {
+        assert_eq!(parse_cfs_quota("800000\n", "100000\n"), Some(8));
+        assert_eq!(parse_cfs_quota("-1\n", "100000\n"), None);
+        assert_eq!(parse_cfs_quota("100000", "0"), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let flag = ThreadCount::resolve(Some("12"), Some("3")).unwrap();
+        assert_eq!(
+            flag,
+            ThreadCount {
+                threads: 12,
+                source: Source::Flag
+            }
+        );
+        assert_eq!(flag.to_string(), "12 threads (--threads)");
+        let env = ThreadCount::resolve(None, Some(" 3 ")).unwrap();
+        assert_eq!(env.threads, 3);
+        assert_eq!(env.source, Source::Environment);
+
+        // 环境变量为空时和没有设置一样
+        let detected = ThreadCount::resolve(None, Some("")).unwrap();
+        assert_eq!(detected, detect());
+        assert!(detected.threads >= 1);
+
+        assert_eq!(
+            ThreadCount::resolve(Some("0"), None),
+            Err(
+                "invalid thread count \"0\" from --threads, expected a positive integer"
+                    .to_string()
+            )
+        );
+        assert!(ThreadCount::resolve(None, Some("many")).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use crate::concurrency::draw::{encode_image, render};
+use crate::concurrency::parse::pixel_to_point;
+use crate::concurrency::viewport::Viewport;
+use num::Complex;
+use std::collections::{HashMap, VecDeque};
+
+/// 每块瓦片的宽和高（像素）。
+pub const TILE_SIZE: usize = 256;
+
+/// 支持的最大缩放级别。再往下`f64`的精度已经不够区分相邻的像素了。
+pub const MAX_ZOOM: u32 = 40;
+
+/// 缩放级别 0 时唯一一块瓦片覆盖的区域。
+const WORLD_UPPER_LEFT: Complex<f64> = Complex { re: -2.5, im: 2.0 };
+const WORLD_LOWER_RIGHT: Complex<f64> = Complex { re: 1.5, im: -2.0 };
+
+/// 瓦片坐标：（缩放级别，列，行），和常见的网页地图一样。缩放级别超过 32 时
+/// 每边的瓦片数超出了`u32`，所以列和行用`u64`。
+pub type TileId = (u32, u64, u64);
+
+/// 返回瓦片`tile`覆盖的复平面区域。
+///
+/// 缩放级别为`z`时，整个世界被看作一张`TILE_SIZE * 2^z`见方的大图，
+/// 瓦片`(z, x, y)`是其中左上角像素为`(x * TILE_SIZE, y * TILE_SIZE)`
+/// 的那一块，再用`pixel_to_point`换算成复平面上的点。
+/// 如果瓦片坐标超出了范围，就返回`None`。
+pub fn tile_viewport(tile: TileId) -> Option<Viewport> {
+    let (z, x, y) = tile;
+    if z > MAX_ZOOM || x >= 1 << z || y >= 1 << z {
+        return None;
+    }
+    let side = TILE_SIZE << z;
+    let world = (side, side);
+    let (left, top) = (x as usize * TILE_SIZE, y as usize * TILE_SIZE);
+    Some(Viewport::new(
+        pixel_to_point(world, (left, top), WORLD_UPPER_LEFT, WORLD_LOWER_RIGHT),
+        pixel_to_point(
+            world,
+            (left + TILE_SIZE, top + TILE_SIZE),
+            WORLD_UPPER_LEFT,
+            WORLD_LOWER_RIGHT,
+        ),
+    ))
+}
+
+/// 把形如`/z/x/y.png`的请求路径解析成瓦片坐标。
+pub fn parse_tile_path(path: &str) -> Option<TileId> {
+    let rest = path.strip_prefix('/')?.strip_suffix(".png")?;
+    let mut parts = rest.split('/');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(z), Some(x), Some(y), None) => {
+            Some((z.parse().ok()?, x.parse().ok()?, y.parse().ok()?))
+        }
+        _ => None,
+    }
+}
+
+/// 渲染瓦片`tile`并编码成 PNG。如果瓦片坐标超出了范围，就返回`None`。
+pub fn render_tile(tile: TileId) -> Option<Vec<u8>> {
+    let viewport = tile_viewport(tile)?;
+    let bounds = (TILE_SIZE, TILE_SIZE);
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    render(
+        &mut pixels,
+        bounds,
+        viewport.upper_left,
+        viewport.lower_right,
+    );
+
+    let mut png = Vec::new();
+    encode_image(&mut png, &pixels, bounds).expect("writing to a Vec cannot fail");
+    Some(png)
+}
+
+/// 最多保存`capacity`块已编码瓦片的 LRU 缓存。
+pub struct TileCache {
+    capacity: usize,
+    tiles: HashMap<TileId, Vec<u8>>,
+    /// 最近使用的瓦片在队尾。
+    order: VecDeque<TileId>,
+}
+
+impl TileCache {
+    pub fn new(capacity: usize) -> TileCache {
+        TileCache {
+            capacity,
+            tiles: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// 查找瓦片，找到时把它标记为最近使用过。
+    pub fn get(&mut self, tile: TileId) -> Option<Vec<u8>> {
+        let png = self.tiles.get(&tile)?.clone();
+        self.touch(tile);
+        Some(png)
+    }
+
+    /// 放入一块瓦片，缓存满了就淘汰最久没有使用的瓦片。
+    pub fn insert(&mut self, tile: TileId, png: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.tiles.insert(tile, png).is_some() {
+            self.touch(tile);
+            return;
+        }
+        self.order.push_back(tile);
+        if self.order.len() > self.capacity
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.tiles.remove(&oldest);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    fn touch(&mut self, tile: TileId) {
+        if let Some(index) = self.order.iter().position(|&t| t == tile) {
+            self.order.remove(index);
+        }
+        self.order.push_back(tile);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tile_viewport() {
+        let world = tile_viewport((0, 0, 0)).unwrap();
+        assert_eq!(world.upper_left, WORLD_UPPER_LEFT);
+        assert_eq!(world.lower_right, WORLD_LOWER_RIGHT);
+
+        // 缩放级别 1 的右下角瓦片是世界的右下四分之一
+        let tile = tile_viewport((1, 1, 1)).unwrap();
+        assert_eq!(tile.upper_left, Complex { re: -0.5, im: 0.0 });
+        assert_eq!(tile.lower_right, WORLD_LOWER_RIGHT);
+
+        assert_eq!(tile_viewport((1, 2, 0)), None);
+        // 缩放级别 32 以上的瓦片坐标总在范围内
+        let deep = tile_viewport((35, 5, 5)).unwrap();
+        let side = (WORLD_LOWER_RIGHT.re - WORLD_UPPER_LEFT.re) / (1u64 << 35) as f64;
+        assert!((deep.width() - side).abs() < side * 1e-3);
+        assert!(tile_viewport((32, u32::MAX.into(), u32::MAX.into())).is_some());
+        assert_eq!(tile_viewport((31, 1 << 31, 0)), None);
+        assert_eq!(tile_viewport((MAX_ZOOM + 1, 0, 0)), None);
+
+        // 最深一级的最后一块瓦片正好贴着世界的右下角
+        let last = (1 << MAX_ZOOM) - 1;
+        let corner = tile_viewport((MAX_ZOOM, last, last)).unwrap();
+        assert_eq!(corner.lower_right, WORLD_LOWER_RIGHT);
+        assert!(corner.upper_left.re < WORLD_LOWER_RIGHT.re);
+        assert!(corner.upper_left.im > WORLD_LOWER_RIGHT.im);
+        assert_eq!(tile_viewport((MAX_ZOOM, last + 1, last)), None);
+    }
+
+    #[test]
+    fn test_parse_tile_path() {
+        assert_eq!(parse_tile_path("/3/2/5.png"), Some((3, 2, 5)));
+        assert_eq!(parse_tile_path("/3/2.png"), None);
+        assert_eq!(parse_tile_path("/3/2/5/1.png"), None);
+        assert_eq!(parse_tile_path("/3/2/5"), None);
+        assert_eq!(parse_tile_path("/a/2/5.png"), None);
+        assert_eq!(
+            parse_tile_path("/40/1099511627775/7.png"),
+            Some((40, (1 << 40) - 1, 7))
+        );
+    }
+
+    #[test]
+    fn test_render_tile() {
+        let png = render_tile((0, 0, 0)).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+        assert_eq!(render_tile((0, 1, 0)), None);
+    }
+
+    #[test]
+    fn test_tile_cache() {
+        let mut cache = TileCache::new(2);
+        cache.insert((0, 0, 0), vec![0]);
+        cache.insert((1, 0, 0), vec![1]);
+        // 访问 (0, 0, 0) 之后，最久没用的是 (1, 0, 0)
+        assert_eq!(cache.get((0, 0, 0)), Some(vec![0]));
+        cache.insert((1, 1, 0), vec![2]);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get((1, 0, 0)), None);
+        assert_eq!(cache.get((0, 0, 0)), Some(vec![0]));
+        assert_eq!(cache.get((1, 1, 0)), Some(vec![2]));
+    }
+}
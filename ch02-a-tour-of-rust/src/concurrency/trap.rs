@@ -0,0 +1,218 @@
+use crate::concurrency::draw::Band;
+use crate::concurrency::mandelbrot::escape_time_orbit;
+use crate::concurrency::palette::heat;
+use crate::concurrency::parse::parse_complex;
+use num::Complex;
+use std::fmt;
+use std::str::FromStr;
+
+/// 轨道陷阱的形状。着色时记录轨迹到陷阱的最近距离。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trap {
+    /// 一个点。
+    Point(Complex<f64>),
+    /// 经过`point`、与实轴夹角为`angle`（弧度）的直线。
+    Line { point: Complex<f64>, angle: f64 },
+    /// 圆心为`center`、半径为`radius`的圆周。
+    Circle { center: Complex<f64>, radius: f64 },
+    /// 经过`center`、分别平行于实轴和虚轴的两条直线。
+    Cross(Complex<f64>),
+}
+
+impl Trap {
+    /// 点`z`到陷阱的距离。
+    pub fn distance(&self, z: Complex<f64>) -> f64 {
+        match *self {
+            Trap::Point(point) => (z - point).norm(),
+            Trap::Line { point, angle } => {
+                // 旋转到直线和实轴平行，距离就是虚部的绝对值
+                ((z - point) * Complex::from_polar(1.0, -angle)).im.abs()
+            }
+            Trap::Circle { center, radius } => ((z - center).norm() - radius).abs(),
+            Trap::Cross(center) => (z.re - center.re).abs().min((z.im - center.im).abs()),
+        }
+    }
+}
+
+/// 从形如`SHAPE:ARGS`的字符串解析陷阱，参数之间用`;`分隔：
+///
+/// - `point:C`
+/// - `line:C;ANGLE`
+/// - `circle:C;RADIUS`
+/// - `cross:C`
+///
+/// 其中`C`是`parse_complex`能解析的复数，例如`0,0`或者`0.25+0.5i`。
+impl FromStr for Trap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Trap, String> {
+        let (shape, args) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected SHAPE:ARGS, found {:?}", s))?;
+        let (point, argument) = match args.split_once(';') {
+            Some((point, argument)) => (point, Some(argument)),
+            None => (args, None),
+        };
+        let point = parse_complex(point).map_err(|err| format!("trap point: {}", err))?;
+        let number = |name: &str| -> Result<f64, String> {
+            let text = argument.ok_or_else(|| format!("{} trap needs a {}", shape, name))?;
+            text.trim()
+                .parse()
+                .map_err(|_| format!("invalid {} {:?}", name, text))
+        };
+        let no_number = || match argument {
+            Some(extra) => Err(format!(
+                "unexpected argument {:?} for {} trap",
+                extra, shape
+            )),
+            None => Ok(()),
+        };
+
+        match shape.trim() {
+            "point" => no_number().map(|_| Trap::Point(point)),
+            "line" => Ok(Trap::Line {
+                point,
+                angle: number("angle")?,
+            }),
+            "circle" => Ok(Trap::Circle {
+                center: point,
+                radius: number("radius")?,
+            }),
+            "cross" => no_number().map(|_| Trap::Cross(point)),
+            other => Err(format!(
+                "unknown trap shape {:?} (expected point, line, circle or cross)",
+                other
+            )),
+        }
+    }
+}
+
+/// 输出`from_str`能解析的格式。
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Trap::Point(p) => write!(f, "point:{},{}", p.re, p.im),
+            Trap::Line { point, angle } => write!(f, "line:{},{};{}", point.re, point.im, angle),
+            Trap::Circle { center, radius } => {
+                write!(f, "circle:{},{};{}", center.re, center.im, radius)
+            }
+            Trap::Cross(c) => write!(f, "cross:{},{}", c.re, c.im),
+        }
+    }
+}
+
+/// 返回`c`的轨迹（最多迭代`limit`次）到陷阱`trap`的最近距离。
+pub fn trap_distance(c: Complex<f64>, limit: usize, trap: &Trap) -> f64 {
+    let mut nearest = f64::INFINITY;
+    escape_time_orbit(c, limit, |_, z| nearest = nearest.min(trap.distance(z)));
+    nearest
+}
+
+/// 用轨道陷阱给条带`band`着色，结果写入 RGB 缓冲区`pixels`。
+///
+/// 其它参数的含义和`render_band`相同。轨迹离陷阱越近，颜色越亮。
+/// 集合内外的点都按轨迹着色。
+pub fn render_orbit_trap(pixels: &mut [u8], band: Band, trap: &Trap) {
+    let bounds = band.size();
+    assert_eq!(pixels.len(), bounds.0 * bounds.1 * 3);
+
+    for row in 0..bounds.1 {
+        for column in 0..bounds.0 {
+            let point = band.point(column, row);
+            let distance = trap_distance(point, 255, trap);
+            let offset = (row * bounds.0 + column) * 3;
+            pixels[offset..offset + 3].copy_from_slice(&heat(1.0 - distance.sqrt().min(1.0)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGIN: Complex<f64> = Complex { re: 0.0, im: 0.0 };
+
+    #[test]
+    fn test_distance() {
+        let z = Complex { re: 3.0, im: 4.0 };
+        assert_eq!(Trap::Point(ORIGIN).distance(z), 5.0);
+        assert_eq!(
+            Trap::Circle {
+                center: ORIGIN,
+                radius: 2.0
+            }
+            .distance(z),
+            3.0
+        );
+        assert_eq!(Trap::Cross(ORIGIN).distance(z), 3.0);
+        let real_axis = Trap::Line {
+            point: ORIGIN,
+            angle: 0.0,
+        };
+        assert_eq!(real_axis.distance(z), 4.0);
+        let imaginary_axis = Trap::Line {
+            point: ORIGIN,
+            angle: std::f64::consts::FRAC_PI_2,
+        };
+        assert!((imaginary_axis.distance(z) - 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_parse_trap() {
+        assert_eq!("point:0,0".parse(), Ok(Trap::Point(ORIGIN)));
+        assert_eq!(
+            "circle:0.25+0.5i; 0.1".parse(),
+            Ok(Trap::Circle {
+                center: Complex { re: 0.25, im: 0.5 },
+                radius: 0.1
+            })
+        );
+        assert!("circle:0,0".parse::<Trap>().is_err());
+        assert!("point:0,0;1".parse::<Trap>().is_err());
+        assert!("square:0,0".parse::<Trap>().is_err());
+        assert!("point".parse::<Trap>().is_err());
+
+        for trap in [
+            Trap::Point(Complex { re: -0.5, im: 0.25 }),
+            Trap::Line {
+                point: ORIGIN,
+                angle: 0.5,
+            },
+            Trap::Circle {
+                center: ORIGIN,
+                radius: 0.5,
+            },
+            Trap::Cross(Complex { re: 1.0, im: -1.0 }),
+        ] {
+            assert_eq!(trap.to_string().parse(), Ok(trap));
+        }
+    }
+
+    #[test]
+    fn test_trap_distance() {
+        // c = 0 的轨迹一直是 0，正好落在原点陷阱上
+        assert_eq!(trap_distance(ORIGIN, 10, &Trap::Point(ORIGIN)), 0.0);
+        // c = 1 的轨迹是 1, 2, 5，离点 2 最近的是 2 本身
+        let c = Complex { re: 1.0, im: 0.0 };
+        assert_eq!(
+            trap_distance(c, 10, &Trap::Point(Complex { re: 2.0, im: 0.0 })),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_render_orbit_trap() {
+        let bounds = (8, 6);
+        let mut pixels = vec![0; bounds.0 * bounds.1 * 3];
+        render_orbit_trap(
+            &mut pixels,
+            Band::whole(
+                bounds,
+                Complex { re: -2.0, im: 1.5 },
+                Complex { re: 1.0, im: -1.5 },
+            ),
+            &Trap::Cross(ORIGIN),
+        );
+        assert!(pixels.iter().any(|&p| p > 0));
+    }
+}
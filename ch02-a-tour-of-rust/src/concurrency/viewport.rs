@@ -0,0 +1,107 @@
+use num::Complex;
+use std::fmt;
+
+/// 复平面上的一个矩形区域，由左上角和右下角两个点确定。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>,
+}
+
+impl Viewport {
+    pub fn new(upper_left: Complex<f64>, lower_right: Complex<f64>) -> Viewport {
+        Viewport {
+            upper_left,
+            lower_right,
+        }
+    }
+
+    /// 区域在实轴方向上的宽度。
+    pub fn width(&self) -> f64 {
+        self.lower_right.re - self.upper_left.re
+    }
+
+    /// 区域在虚轴方向上的高度。
+    pub fn height(&self) -> f64 {
+        self.upper_left.im - self.lower_right.im
+    }
+
+    /// 区域的中心点。
+    pub fn center(&self) -> Complex<f64> {
+        (self.upper_left + self.lower_right) / 2.0
+    }
+
+    /// 平移区域。`dx`和`dy`以区域的宽和高为单位，向右、向上为正。
+    pub fn pan(&self, dx: f64, dy: f64) -> Viewport {
+        let offset = Complex {
+            re: dx * self.width(),
+            im: dy * self.height(),
+        };
+        Viewport::new(self.upper_left + offset, self.lower_right + offset)
+    }
+
+    /// 把区域平移整数个像素，`bounds`是区域对应的图像的宽和高。
+    /// `columns`向右为正，`rows`向下为正，和像素坐标的方向一致。
+    pub fn pan_pixels(&self, bounds: (usize, usize), columns: isize, rows: isize) -> Viewport {
+        self.pan(
+            columns as f64 / bounds.0 as f64,
+            -rows as f64 / bounds.1 as f64,
+        )
+    }
+
+    /// 以中心为不动点缩放区域。`factor`大于 1 时放大（看到的区域变小），
+    /// 小于 1 时缩小。
+    pub fn zoom(&self, factor: f64) -> Viewport {
+        let center = self.center();
+        Viewport::new(
+            center + (self.upper_left - center) / factor,
+            center + (self.lower_right - center) / factor,
+        )
+    }
+}
+
+/// 以命令行参数的格式输出，例如`-1.2,0.35 -1,0.2`。
+impl fmt::Display for Viewport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{} {},{}",
+            self.upper_left.re, self.upper_left.im, self.lower_right.re, self.lower_right.im
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit() -> Viewport {
+        Viewport::new(Complex { re: -1.0, im: 1.0 }, Complex { re: 1.0, im: -1.0 })
+    }
+
+    #[test]
+    fn test_pan() {
+        let moved = unit().pan(0.25, -0.5);
+        assert_eq!(moved.upper_left, Complex { re: -0.5, im: 0.0 });
+        assert_eq!(moved.lower_right, Complex { re: 1.5, im: -2.0 });
+    }
+
+    #[test]
+    fn test_pan_pixels() {
+        let moved = unit().pan_pixels((8, 4), 2, 1);
+        assert_eq!(moved, unit().pan(0.25, -0.25));
+    }
+
+    #[test]
+    fn test_zoom() {
+        let zoomed = unit().zoom(2.0);
+        assert_eq!(zoomed.upper_left, Complex { re: -0.5, im: 0.5 });
+        assert_eq!(zoomed.lower_right, Complex { re: 0.5, im: -0.5 });
+        assert_eq!(zoomed.zoom(0.5), unit());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(unit().to_string(), "-1,1 1,-1");
+    }
+}
@@ -1,45 +1,1078 @@
-use ch02::concurrency::draw::{render, write_image};
-use ch02::concurrency::parse::{parse_complex, parse_pair, pixel_to_point};
+use ch02::concurrency::area::{KNOWN_AREA, MonteCarlo, SAMPLES_PER_BATCH, grid_area};
+use ch02::concurrency::buddhabrot::{Sampling, render_buddhabrot};
+use ch02::concurrency::contour::{Level, marching_squares, write_svg};
+use ch02::concurrency::distributed::{Coordinator, Job, work};
+use ch02::concurrency::draw::{
+    render_band, render_bands, render_distance, render_subdivide, write_image, write_image_rgb,
+};
+use ch02::concurrency::explorer::{Search, search};
+use ch02::concurrency::formula::{Formula, render_formula};
+use ch02::concurrency::heightmap::{Field, HeightField, iteration_field};
+use ch02::concurrency::lyapunov::{Lyapunov, Scheme, Sequence, render_lyapunov};
+use ch02::concurrency::mandelbulb::{Bulb, Camera, Vec3, render_mandelbulb, screen_corners};
+use ch02::concurrency::newton::{Polynomial, render_newton};
+use ch02::concurrency::nucleus::{estimate_period, find_nucleus};
+use ch02::concurrency::parse::{parse_complex, parse_tuple};
+use ch02::concurrency::preview::explore;
+use ch02::concurrency::probe::{Classification, plot_orbit, probe};
+use ch02::concurrency::rays::{Angle, MIN_EQUIPOTENTIAL_RADIUS, Overlay};
+use ch02::concurrency::scene::Scene;
+use ch02::concurrency::stats::render_bands_with_stats;
+use ch02::concurrency::threads::{THREADS_ENV, ThreadCount, pin_bands};
+use ch02::concurrency::trap::{Trap, render_orbit_trap};
+use ch02::concurrency::viewport::Viewport;
+use num::Complex;
 use std::env;
+use std::fs;
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 5 {
-        eprintln!("Usage: {} FILE PIXELS UPPERLEFT LOWERRIGHT", args[0]);
+    let mut args: Vec<String> = env::args().collect();
+    // 这几个子命令不渲染，线程数对它们没有意义，不用报告
+    let renders = !matches!(
+        args.get(1).map(String::as_str),
+        Some("coordinate" | "nucleus" | "probe")
+    );
+    let threads = thread_count(&mut args, renders);
+    match args.get(1).map(String::as_str) {
+        Some("area") => {
+            args.remove(1);
+            return area(args, threads);
+        }
+        Some("buddhabrot") => {
+            args.remove(1);
+            return buddhabrot(args, threads);
+        }
+        Some("contour") => {
+            args.remove(1);
+            return contour(args, threads);
+        }
+        Some("coordinate") => {
+            args.remove(1);
+            return coordinate(args);
+        }
+        Some("explore") => {
+            args.remove(1);
+            return auto_explore(args, threads);
+        }
+        Some("heightmap") => {
+            args.remove(1);
+            return heightmap(args, threads);
+        }
+        Some("lyapunov") => {
+            args.remove(1);
+            return lyapunov(args, threads);
+        }
+        Some("mandelbulb") => {
+            args.remove(1);
+            return mandelbulb(args, threads);
+        }
+        Some("probe") => {
+            args.remove(1);
+            return probe_point(args);
+        }
+        Some("newton") => {
+            args.remove(1);
+            return newton(args, threads);
+        }
+        Some("nucleus") => {
+            args.remove(1);
+            return nucleus(args);
+        }
+        Some("work") => {
+            args.remove(1);
+            return worker(args, threads);
+        }
+        _ => {}
+    }
+
+    let distance = take_flag(&mut args, "--distance");
+    let preview = take_flag(&mut args, "--preview");
+    let subdivide = take_flag(&mut args, "--subdivide");
+    // `--stats`输出表格，`--stats=json`输出 JSON
+    let stats_format = match take_option(&mut args, "--stats") {
+        Some(format) => Some(format),
+        None if take_flag(&mut args, "--stats") => Some("table".to_string()),
+        None => None,
+    };
+    if let Some(format) = &stats_format
+        && format != "table"
+        && format != "json"
+    {
         eprintln!(
-            "Example: {} mandel.png 1000x750 -1.20,0.35 -1,0.20",
-            args[0]
+            "unknown stats format {:?}, expected `table` or `json`",
+            format
         );
         std::process::exit(1);
     }
-    let bounds = parse_pair(&args[2], 'x').expect("error parsing image dimensions");
-    let upper_left = parse_complex(&args[3]).expect("error parsing upper left corner point");
-    let lower_right = parse_complex(&args[4]).expect("error parsing lower right corner point");
-    let mut pixels = vec![0; bounds.0 * bounds.1];
+    let rays = take_option(&mut args, "--rays");
+    let equipotentials = take_option(&mut args, "--equipotentials");
+    let scene_path = take_option(&mut args, "--scene");
+    let trap_spec = take_option(&mut args, "--trap");
+    let formula_spec = take_option(&mut args, "--formula");
+    let scene = match scene_path {
+        Some(path) if args.len() == 2 => scene_arg(&path),
+        None if args.len() == 5 => Scene {
+            bounds: bounds_arg(&args[2]),
+            viewport: Viewport::new(
+                complex_arg(&args[3], "upper left corner point"),
+                complex_arg(&args[4], "lower right corner point"),
+            ),
+            trap: None,
+            formula: None,
+        },
+        _ => {
+            eprintln!(
+                "Usage: {} [--distance] [--subdivide] [--preview] [--stats[=json]] [--rays=P/Q,...] [--equipotentials=R,...] [--trap=SHAPE:ARGS] [--formula=EXPR] FILE PIXELS UPPERLEFT LOWERRIGHT",
+                args[0]
+            );
+            eprintln!("       {} [OPTIONS] --scene=SCENE FILE", args[0]);
+            eprintln!(
+                "Every command also accepts --threads=N (or {}=N) and --pin",
+                THREADS_ENV
+            );
+            eprintln!(
+                "Example: {} mandel.png 1000x750 -1.20,0.35 -1,0.20",
+                args[0]
+            );
+            eprintln!(
+                "Example: {} --trap='circle:0,0;0.5' trap.png 1000x750 -2,1.2 1,-1.2",
+                args[0]
+            );
+            eprintln!(
+                "Example: {} --formula='conj(z)^2 + c' tricorn.png 1000x750 -2,1.2 1,-1.2",
+                args[0]
+            );
+            eprintln!(
+                "Example: {} --rays=1/3,2/7 --equipotentials=2,1.2 rays.png 1000x750 -2,1.2 1,-1.2",
+                args[0]
+            );
+            std::process::exit(1);
+        }
+    };
+    // 命令行上的陷阱优先于场景文件里的陷阱
+    let trap = match trap_spec {
+        Some(spec) => Some(spec.parse::<Trap>().unwrap_or_else(|err| {
+            eprintln!("error parsing trap: {}", err);
+            std::process::exit(1);
+        })),
+        None => scene.trap,
+    };
+    if distance && subdivide {
+        eprintln!("--distance cannot be combined with --subdivide");
+        std::process::exit(1);
+    }
+    if trap.is_some() && (distance || subdivide) {
+        eprintln!("--trap cannot be combined with --distance or --subdivide");
+        std::process::exit(1);
+    }
+    let formula = match formula_spec {
+        Some(spec) => Some(spec.parse::<Formula>().unwrap_or_else(|err| {
+            eprintln!("error parsing formula: {}", err);
+            eprintln!("{}", err.pointer(&spec));
+            std::process::exit(1);
+        })),
+        None => scene.formula,
+    };
+    if formula.is_some() && (trap.is_some() || distance || subdivide) {
+        eprintln!("--formula cannot be combined with --trap, --distance or --subdivide");
+        std::process::exit(1);
+    }
+    if stats_format.is_some() && (formula.is_some() || trap.is_some() || distance || subdivide) {
+        eprintln!("--stats is only available for the plain escape-time render");
+        std::process::exit(1);
+    }
+    let overlay = overlay_args(rays, equipotentials);
+    if !overlay.is_empty() && trap.is_some() {
+        eprintln!("--rays and --equipotentials cannot be combined with --trap");
+        std::process::exit(1);
+    }
+    let bounds = scene.bounds;
+    let Viewport {
+        upper_left,
+        lower_right,
+    } = scene.viewport;
+
+    if preview {
+        // 先在终端里预览并调整区域，再把最终坐标打印出来供完整渲染使用
+        let viewport = explore(scene.viewport).expect("error running terminal preview");
+        println!(
+            "upper_left:  {},{}",
+            viewport.upper_left.re, viewport.upper_left.im
+        );
+        println!(
+            "lower_right: {},{}",
+            viewport.lower_right.re, viewport.lower_right.im
+        );
+        println!(
+            "{} {} {}x{} {}",
+            args[0], args[1], bounds.0, bounds.1, viewport
+        );
+        return;
+    }
 
     // 多线程
-    let threads = 8;
-    let rows_per_thread = bounds.1 / threads;
-    {
-        let bands: Vec<&mut [u8]> = pixels.chunks_mut(rows_per_thread * bounds.0).collect();
-        crossbeam::scope(|spawner| {
-            for (i, band) in bands.into_iter().enumerate() {
-                let top = rows_per_thread * i;
-                let height = band.len() / bounds.0;
-                let band_bounds = (bounds.0, height);
-                let band_upper_left = pixel_to_point(bounds, (0, top), upper_left, lower_right);
-                let band_lower_right =
-                    pixel_to_point(bounds, (bounds.0, top + height), upper_left, lower_right);
-
-                spawner.spawn(move |_| {
-                    render(band, band_bounds, band_upper_left, band_lower_right);
-                });
+
+    if let Some(trap) = trap {
+        let mut pixels = vec![0; bounds.0 * bounds.1 * 3];
+        render_bands(
+            &mut pixels,
+            bounds,
+            upper_left,
+            lower_right,
+            threads,
+            |pixels, band| render_orbit_trap(pixels, band, &trap),
+        );
+        write_image_rgb(&args[1], &pixels, bounds).expect("error writing PNG file");
+        return;
+    }
+
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    if let Some(formula) = formula {
+        render_bands(
+            &mut pixels,
+            bounds,
+            upper_left,
+            lower_right,
+            threads,
+            |pixels, band| render_formula(pixels, band, &formula),
+        );
+        write_gray(&args[1], &pixels, bounds, scene.viewport, &overlay);
+        return;
+    }
+
+    if let Some(format) = stats_format {
+        let stats = render_bands_with_stats(&mut pixels, bounds, upper_left, lower_right, threads);
+        write_gray(&args[1], &pixels, bounds, scene.viewport, &overlay);
+        if format == "json" {
+            println!("{}", stats.to_json());
+        } else {
+            print!("{}", stats.to_table());
+        }
+        return;
+    }
+
+    // 几种渲染方式的签名相同，可以当作函数指针传给各个线程
+    let renderer = if distance {
+        render_distance
+    } else if subdivide {
+        render_subdivide
+    } else {
+        render_band
+    };
+    render_bands(
+        &mut pixels,
+        bounds,
+        upper_left,
+        lower_right,
+        threads,
+        renderer,
+    );
+
+    write_gray(&args[1], &pixels, bounds, scene.viewport, &overlay);
+}
+
+/// 按`--threads=N`、环境变量`MANDELBROT_THREADS`或者自动检测决定渲染用的线程数，
+/// 有`--pin`时把渲染线程固定到核心上。`report`为真时把选定的配置打印到标准错误输出。
+fn thread_count(args: &mut Vec<String>, report: bool) -> usize {
+    let pin = take_flag(args, "--pin");
+    let flag = take_option(args, "--threads");
+    let env = env::var(THREADS_ENV).ok();
+    let count = ThreadCount::resolve(flag.as_deref(), env.as_deref()).unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    });
+    let pinning = match pin.then(pin_bands) {
+        None => String::new(),
+        Some(0) => ", CPU pinning not supported on this platform".to_string(),
+        Some(1) => ", render threads pinned to 1 core".to_string(),
+        Some(cores) => format!(", render threads pinned to {} cores", cores),
+    };
+    if report {
+        eprintln!("Using {}{}", count, pinning);
+    }
+    count.threads
+}
+
+/// 外部射线的颜色和等势线的颜色。
+const RAY_COLOR: [u8; 3] = [255, 64, 64];
+const EQUIPOTENTIAL_COLOR: [u8; 3] = [64, 160, 255];
+
+/// 按`--rays`和`--equipotentials`的值（逗号分隔的角度和 Böttcher 坐标的模）
+/// 追踪要叠加的线条。
+fn overlay_args(rays: Option<String>, equipotentials: Option<String>) -> Overlay {
+    let mut overlay = Overlay::default();
+    for angle in rays.iter().flat_map(|rays| rays.split(',')) {
+        let angle: Angle = angle.parse().unwrap_or_else(|err| {
+            eprintln!("error parsing ray angle: {}", err);
+            std::process::exit(1);
+        });
+        overlay.add_ray(angle, RAY_COLOR);
+    }
+    for radius in equipotentials.iter().flat_map(|radii| radii.split(',')) {
+        match radius.trim().parse::<f64>() {
+            Ok(radius) if radius >= MIN_EQUIPOTENTIAL_RADIUS => {
+                overlay.add_equipotential(radius, EQUIPOTENTIAL_COLOR)
+            }
+            _ => {
+                eprintln!(
+                    "error parsing equipotential: expected a number of at least {}, found {:?}",
+                    MIN_EQUIPOTENTIAL_RADIUS, radius
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+    overlay
+}
+
+/// 写入灰度图像。有叠加的线条时先把它们画上去，写成 RGB 图像。
+fn write_gray(
+    filename: &str,
+    pixels: &[u8],
+    bounds: (usize, usize),
+    viewport: Viewport,
+    overlay: &Overlay,
+) {
+    let written = if overlay.is_empty() {
+        write_image(filename, pixels, bounds)
+    } else {
+        let rgb = overlay.composite(pixels, bounds, viewport.upper_left, viewport.lower_right);
+        write_image_rgb(filename, &rgb, bounds)
+    };
+    written.expect("error writing PNG file");
+}
+
+/// 蒙特卡罗估计每运行这么多批就保存一次检查点。
+const CHECKPOINT_BATCHES: u64 = 100;
+
+/// `area`子命令：用网格计数或者蒙特卡罗方法估计曼德勃罗集的面积。
+fn area(mut args: Vec<String>, threads: usize) {
+    let grid = take_option(&mut args, "--grid");
+    let samples = take_option(&mut args, "--samples").unwrap_or_else(|| "1000000".to_string());
+    let limit = take_option(&mut args, "--limit").unwrap_or_else(|| "10000".to_string());
+    let seed = take_option(&mut args, "--seed").unwrap_or_else(|| "0".to_string());
+    let checkpoint = take_option(&mut args, "--checkpoint");
+    if args.len() != 1 {
+        eprintln!(
+            "Usage: {} area [--grid=CELLS_PER_UNIT | --samples=N [--seed=N] [--checkpoint=FILE]] [--limit=N]",
+            args[0]
+        );
+        eprintln!(
+            "Example: {} area --samples=100000000 --checkpoint=area.txt",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+    let limit: usize = limit.parse().expect("error parsing iteration limit");
+
+    if let Some(resolution) = grid {
+        let resolution: usize = resolution.parse().expect("error parsing grid resolution");
+        let estimate = grid_area(resolution, limit, threads);
+        println!(
+            "method:   grid ({} cells per unit, limit {})",
+            resolution, limit
+        );
+        println!("area:     {} (boundary cells)", estimate);
+        print_known_area(estimate.contains(KNOWN_AREA));
+        return;
+    }
+
+    let samples: u64 = samples.parse().expect("error parsing sample count");
+    let seed: u64 = seed.parse().expect("error parsing seed");
+    // 检查点存在时从它继续，参数必须和上次一样
+    let mut monte_carlo = match checkpoint.as_deref().map(fs::read_to_string) {
+        Some(Ok(text)) => {
+            let saved: MonteCarlo = text.parse().unwrap_or_else(|err| {
+                eprintln!("error parsing checkpoint: {}", err);
+                std::process::exit(1);
+            });
+            if saved.seed != seed || saved.limit != limit {
+                eprintln!(
+                    "checkpoint was made with --seed={} --limit={}",
+                    saved.seed, saved.limit
+                );
+                std::process::exit(1);
             }
+            eprintln!("resuming after {} samples", saved.samples());
+            saved
+        }
+        // 只有检查点还不存在时才从头开始，别的错误继续运行会覆盖掉它
+        Some(Err(err)) if err.kind() != std::io::ErrorKind::NotFound => {
+            eprintln!("error reading checkpoint: {}", err);
+            std::process::exit(1);
+        }
+        _ => MonteCarlo::new(seed, limit),
+    };
+
+    let batches = samples.div_ceil(SAMPLES_PER_BATCH);
+    while monte_carlo.batches < batches {
+        let chunk = CHECKPOINT_BATCHES.min(batches - monte_carlo.batches);
+        monte_carlo.run(chunk, threads);
+        if let Some(path) = &checkpoint {
+            write_checkpoint(path, &monte_carlo).expect("error writing checkpoint");
+            eprintln!(
+                "{} / {} samples: {}",
+                monte_carlo.samples(),
+                batches * SAMPLES_PER_BATCH,
+                monte_carlo.estimate()
+            );
+        }
+    }
+
+    let estimate = monte_carlo.estimate();
+    println!(
+        "method:   monte carlo ({} samples, limit {}, seed {})",
+        monte_carlo.samples(),
+        limit,
+        seed
+    );
+    println!("area:     {} (95% confidence)", estimate);
+    print_known_area(estimate.contains(KNOWN_AREA));
+}
+
+fn print_known_area(contains: bool) {
+    println!(
+        "known:    {} ({} the interval)",
+        KNOWN_AREA,
+        if contains { "inside" } else { "outside" }
+    );
+}
+
+/// 先写到`PATH.tmp`再改名，写到一半被打断时原来的检查点还是完整的。
+fn write_checkpoint(path: &str, monte_carlo: &MonteCarlo) -> std::io::Result<()> {
+    let temporary = format!("{}.tmp", path);
+    fs::write(&temporary, monte_carlo.to_string())?;
+    fs::rename(&temporary, path)
+}
+
+/// `buddhabrot`子命令：渲染 Buddhabrot / Nebulabrot，写入 RGB 图像。
+fn buddhabrot(mut args: Vec<String>, threads: usize) {
+    let samples = take_option(&mut args, "--samples").unwrap_or_else(|| "1000000".to_string());
+    let limits = take_option(&mut args, "--limits").unwrap_or_else(|| "5000,500,50".to_string());
+    let seed = take_option(&mut args, "--seed").unwrap_or_else(|| "0".to_string());
+    if args.len() != 5 {
+        eprintln!(
+            "Usage: {} buddhabrot [--samples=N] [--limits=R,G,B] [--seed=N] FILE PIXELS UPPERLEFT LOWERRIGHT",
+            args[0]
+        );
+        eprintln!(
+            "Example: {} buddhabrot --limits=5000,500,50 buddha.png 1000x1000 -2,2 2,-2",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+    let bounds = bounds_arg(&args[2]);
+    let upper_left = complex_arg(&args[3], "upper left corner point");
+    let lower_right = complex_arg(&args[4], "lower right corner point");
+    // 一个上限表示三个通道相同，也可以分别给出 R,G,B 三个上限
+    let limits = parse_tuple::<usize, 3>(&limits, ',', true)
+        .or_else(|err| {
+            limits
+                .trim()
+                .parse()
+                .map(|limit| [limit; 3])
+                .map_err(|_| err)
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("error parsing iteration limits: {}", err);
+            std::process::exit(1);
+        });
+    let sampling = Sampling {
+        limits,
+        samples: samples.parse().expect("error parsing sample count"),
+        seed: seed.parse().expect("error parsing seed"),
+    };
+
+    let mut pixels = vec![0; bounds.0 * bounds.1 * 3];
+    render_buddhabrot(
+        &mut pixels,
+        bounds,
+        upper_left,
+        lower_right,
+        sampling,
+        threads,
+    );
+
+    write_image_rgb(&args[1], &pixels, bounds).expect("error writing PNG file");
+}
+
+/// `explore`子命令：自动寻找值得放大的区域，把排好序的候选区域写成场景文件
+/// 和缩略图。
+fn auto_explore(mut args: Vec<String>, threads: usize) {
+    let defaults = Search::default();
+    let number = |value: Option<String>, default: usize, what: &str| -> usize {
+        value.map_or(default, |v| {
+            v.parse().unwrap_or_else(|_| {
+                eprintln!("error parsing {}: {:?}", what, v);
+                std::process::exit(1);
+            })
         })
-        .expect("error joining threads");
+    };
+    let levels = number(
+        take_option(&mut args, "--levels"),
+        defaults.levels,
+        "levels",
+    );
+    let beam = number(
+        take_option(&mut args, "--beam"),
+        defaults.beam,
+        "beam width",
+    );
+    let seed = number(take_option(&mut args, "--seed"), 0, "seed");
+    let count = number(take_option(&mut args, "--count"), 10, "count");
+    let pixels = take_option(&mut args, "--pixels").unwrap_or_else(|| "1000x750".to_string());
+    let thumbnail = take_option(&mut args, "--thumbnail").unwrap_or_else(|| "160x120".to_string());
+    let out_dir = take_option(&mut args, "--out").unwrap_or_else(|| "explore".to_string());
+    if args.len() != 3 {
+        eprintln!(
+            "Usage: {} explore [--levels=N] [--beam=N] [--seed=N] [--count=N] [--pixels=WxH] [--thumbnail=WxH] [--out=DIR] UPPERLEFT LOWERRIGHT",
+            args[0]
+        );
+        eprintln!("Example: {} explore --seed=3 -2.5,1.5 1.5,-1.5", args[0]);
+        std::process::exit(1);
+    }
+    let start = Viewport::new(
+        complex_arg(&args[1], "upper left corner point"),
+        complex_arg(&args[2], "lower right corner point"),
+    );
+    let bounds = bounds_arg(&pixels);
+    let thumbnail = bounds_arg(&thumbnail);
+
+    let settings = Search {
+        levels,
+        beam,
+        seed: seed as u64,
+        threads,
+        ..defaults
+    };
+    let candidates = search(start, &settings);
+    fs::create_dir_all(&out_dir).expect("error creating output directory");
+
+    println!("rank  score   level  scene");
+    for (rank, candidate) in candidates.iter().take(count).enumerate() {
+        let name = format!("{}/{:02}", out_dir, rank + 1);
+        let scene = Scene {
+            bounds,
+            viewport: candidate.viewport,
+            trap: None,
+            formula: None,
+        };
+        fs::write(format!("{}.scene", name), scene.to_string()).expect("error writing scene file");
+
+        let mut pixels = vec![0; thumbnail.0 * thumbnail.1];
+        render_bands(
+            &mut pixels,
+            thumbnail,
+            candidate.viewport.upper_left,
+            candidate.viewport.lower_right,
+            threads,
+            render_band,
+        );
+        write_image(&format!("{}.png", name), &pixels, thumbnail).expect("error writing PNG file");
+        println!(
+            "{:>4}  {:.4}  {:>5}  {}.scene",
+            rank + 1,
+            candidate.score,
+            candidate.level,
+            name
+        );
+    }
+}
+
+/// 读取并解析场景文件。出错时打印原因并退出。
+fn scene_arg(path: &str) -> Scene {
+    let text = fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("error reading scene file {}: {}", path, err);
+        std::process::exit(1);
+    });
+    text.parse().unwrap_or_else(|err| {
+        eprintln!("error parsing scene file {}: {}", path, err);
+        std::process::exit(1);
+    })
+}
+
+/// 解析命令行参数中的图像尺寸，例如`1000x750`或者`"1000 x 750"`。
+fn bounds_arg(arg: &str) -> (usize, usize) {
+    match parse_tuple(arg, 'x', true) {
+        Ok([width, height]) => (width, height),
+        Err(err) => {
+            eprintln!("error parsing image dimensions: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `lyapunov`子命令：渲染逻辑斯谛映射的李雅普诺夫分形，写入 RGB 图像。
+/// 图像的横坐标是参数`a`，纵坐标是参数`b`。
+fn lyapunov(mut args: Vec<String>, threads: usize) {
+    let sequence = take_option(&mut args, "--sequence").unwrap_or_else(|| "AB".to_string());
+    let iterations = take_option(&mut args, "--iterations");
+    let palette = take_option(&mut args, "--palette").unwrap_or_else(|| "classic".to_string());
+    if args.len() != 5 {
+        eprintln!(
+            "Usage: {} lyapunov [--sequence=AB...] [--iterations=N] [--palette=classic|coolwarm] FILE PIXELS UPPERLEFT LOWERRIGHT",
+            args[0]
+        );
+        eprintln!(
+            "Example: {} lyapunov --sequence=BBBBBBAAAAAA zircon.png 800x800 3.4,4 4,2.5",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+    let sequence: Sequence = sequence.parse().unwrap_or_else(|err| {
+        eprintln!("error parsing sequence: {}", err);
+        std::process::exit(1);
+    });
+    let mut params = Lyapunov::new(sequence);
+    if let Some(iterations) = iterations {
+        params.iterations = iterations.parse().expect("error parsing iteration count");
     }
+    params.scheme = palette.parse::<Scheme>().unwrap_or_else(|err| {
+        eprintln!("error parsing palette: {}", err);
+        std::process::exit(1);
+    });
+    let bounds = bounds_arg(&args[2]);
+    let upper_left = complex_arg(&args[3], "upper left corner point");
+    let lower_right = complex_arg(&args[4], "lower right corner point");
+
+    let mut pixels = vec![0; bounds.0 * bounds.1 * 3];
+    render_bands(
+        &mut pixels,
+        bounds,
+        upper_left,
+        lower_right,
+        threads,
+        |pixels, band| render_lyapunov(pixels, band, &params),
+    );
+
+    write_image_rgb(&args[1], &pixels, bounds).expect("error writing PNG file");
+}
 
+/// `mandelbulb`子命令：用光线步进渲染三维的曼德勃罗球，写入 RGB 图像。
+fn mandelbulb(mut args: Vec<String>, threads: usize) {
+    let defaults = (Camera::default(), Bulb::default());
+    let power = take_option(&mut args, "--power");
+    let iterations = take_option(&mut args, "--iterations");
+    let position = take_option(&mut args, "--camera");
+    let target = take_option(&mut args, "--target");
+    let fov = take_option(&mut args, "--fov");
+    if args.len() != 3 {
+        eprintln!(
+            "Usage: {} mandelbulb [--power=N] [--iterations=N] [--camera=X,Y,Z] [--target=X,Y,Z] [--fov=DEGREES] FILE PIXELS",
+            args[0]
+        );
+        eprintln!(
+            "Example: {} mandelbulb --camera=1.5,1,-2 bulb.png 800x600",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+    let vector = |arg: Option<String>, default: Vec3, what: &str| match arg {
+        Some(arg) => arg.parse().unwrap_or_else(|err| {
+            eprintln!("error parsing {}: {}", what, err);
+            std::process::exit(1);
+        }),
+        None => default,
+    };
+    let camera = Camera {
+        position: vector(position, defaults.0.position, "camera position"),
+        target: vector(target, defaults.0.target, "camera target"),
+        fov: fov.map_or(defaults.0.fov, |fov| {
+            fov.parse().expect("error parsing field of view")
+        }),
+    };
+    if let Err(err) = camera.check() {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+    let bulb = Bulb {
+        power: power.map_or(defaults.1.power, |power| {
+            power.parse().expect("error parsing power")
+        }),
+        iterations: iterations.map_or(defaults.1.iterations, |iterations| {
+            iterations.parse().expect("error parsing iteration count")
+        }),
+    };
+    let bounds = bounds_arg(&args[2]);
+    let (upper_left, lower_right) = screen_corners(bounds);
+
+    let mut pixels = vec![0; bounds.0 * bounds.1 * 3];
+    render_bands(
+        &mut pixels,
+        bounds,
+        upper_left,
+        lower_right,
+        threads,
+        |pixels, band| render_mandelbulb(pixels, band, &camera, &bulb),
+    );
+
+    write_image_rgb(&args[1], &pixels, bounds).expect("error writing PNG file");
+}
+
+/// `newton`子命令：渲染多项式的牛顿分形，写入 RGB 图像。
+fn newton(mut args: Vec<String>, threads: usize) {
+    let poly = take_option(&mut args, "--poly").unwrap_or_else(|| "z^3 - 1".to_string());
+    if args.len() != 5 {
+        eprintln!(
+            "Usage: {} newton [--poly=POLYNOMIAL] FILE PIXELS UPPERLEFT LOWERRIGHT",
+            args[0]
+        );
+        eprintln!(
+            "Example: {} newton --poly='z^5 - 3z + 1' newton.png 1000x1000 -2,2 2,-2",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+    let polynomial: Polynomial = poly.parse().unwrap_or_else(|err| {
+        eprintln!("error parsing polynomial: {}", err);
+        std::process::exit(1);
+    });
+    let bounds = bounds_arg(&args[2]);
+    let upper_left = complex_arg(&args[3], "upper left corner point");
+    let lower_right = complex_arg(&args[4], "lower right corner point");
+
+    let mut pixels = vec![0; bounds.0 * bounds.1 * 3];
+    render_bands(
+        &mut pixels,
+        bounds,
+        upper_left,
+        lower_right,
+        threads,
+        |pixels, band| render_newton(pixels, band, &polynomial),
+    );
+
+    write_image_rgb(&args[1], &pixels, bounds).expect("error writing PNG file");
+}
+
+/// `contour`子命令：提取几个迭代次数的等值线，写成 SVG 矢量图。
+fn contour(mut args: Vec<String>, threads: usize) {
+    let levels = take_option(&mut args, "--levels").unwrap_or_else(|| "5,10,20,50,100".to_string());
+    let field = take_option(&mut args, "--field").unwrap_or_else(|| "smooth".to_string());
+    let limit = take_option(&mut args, "--limit").unwrap_or_else(|| "1000".to_string());
+    let stroke = take_option(&mut args, "--stroke").unwrap_or_else(|| "1".to_string());
+    if args.len() != 5 {
+        eprintln!(
+            "Usage: {} contour [--levels=N[:#RRGGBB],...] [--field=escape|smooth] [--limit=N] [--stroke=WIDTH] FILE PIXELS UPPERLEFT LOWERRIGHT",
+            args[0]
+        );
+        eprintln!(
+            "Example: {} contour --levels=10:#3050ff,40:#ff8000 bands.svg 1000x750 -2,1.2 1,-1.2",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+    let levels: Vec<Level> = levels
+        .split(',')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|err| {
+            eprintln!("error parsing levels: {}", err);
+            std::process::exit(1);
+        });
+    let field: Field = field.parse().unwrap_or_else(|err| {
+        eprintln!("error parsing field: {}", err);
+        std::process::exit(1);
+    });
+    let limit = limit.parse().expect("error parsing iteration limit");
+    let stroke = stroke.parse().expect("error parsing stroke width");
+    let bounds = bounds_arg(&args[2]);
+    let upper_left = complex_arg(&args[3], "upper left corner point");
+    let lower_right = complex_arg(&args[4], "lower right corner point");
+
+    let values = iteration_field(bounds, upper_left, lower_right, limit, field, threads);
+    let contours: Vec<_> = levels
+        .into_iter()
+        .map(|level| (level, marching_squares(&values, bounds, level.value)))
+        .collect();
+    for (level, paths) in &contours {
+        let points: usize = paths.iter().map(Vec::len).sum();
+        println!(
+            "level {:>8}: {} paths, {} points",
+            level.value,
+            paths.len(),
+            points
+        );
+    }
+    let output = fs::File::create(&args[1]).map(std::io::BufWriter::new);
+    output
+        .and_then(|file| write_svg(file, bounds, &contours, stroke))
+        .expect("error writing SVG file");
+}
+
+/// `coordinate`子命令：把图像切成瓦片，分给用`work`子命令启动的工作进程渲染，
+/// 再拼成整幅图像。
+fn coordinate(mut args: Vec<String>) {
+    let listen = take_option(&mut args, "--listen").unwrap_or_else(|| "127.0.0.1:7878".to_string());
+    let tile_size = take_option(&mut args, "--tile");
+    let timeout = take_option(&mut args, "--timeout");
+    if args.len() != 5 {
+        eprintln!(
+            "Usage: {} coordinate [--listen=ADDR:PORT] [--tile=N] [--timeout=SECONDS] FILE PIXELS UPPERLEFT LOWERRIGHT",
+            args[0]
+        );
+        eprintln!(
+            "Example: {} coordinate --listen=0.0.0.0:7878 poster.png 16000x12000 -2,1.2 1,-1.2",
+            args[0]
+        );
+        eprintln!("Then start workers with: {} work HOST:7878", args[0]);
+        std::process::exit(1);
+    }
+    let bounds = bounds_arg(&args[2]);
+    let mut coordinator = Coordinator::new(Job {
+        bounds,
+        upper_left: complex_arg(&args[3], "upper left corner point"),
+        lower_right: complex_arg(&args[4], "lower right corner point"),
+    });
+    if let Some(tile_size) = tile_size {
+        coordinator.tile_size = match tile_size.parse() {
+            Ok(size) if size > 0 => size,
+            _ => {
+                eprintln!("error parsing tile size {:?}", tile_size);
+                std::process::exit(1);
+            }
+        };
+    }
+    if let Some(timeout) = timeout {
+        coordinator.timeout = match timeout.parse::<f64>() {
+            Ok(seconds) if seconds > 0.0 => Duration::from_secs_f64(seconds),
+            _ => {
+                eprintln!("error parsing timeout {:?}", timeout);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    let listener = TcpListener::bind(&listen).unwrap_or_else(|err| {
+        eprintln!("error listening on {}: {}", listen, err);
+        std::process::exit(1);
+    });
+    // 端口写成 0 时由系统挑选，所以打印实际监听的地址
+    println!(
+        "Listening on {}",
+        listener.local_addr().expect("error reading local address")
+    );
+    let (pixels, summary) = coordinator.run(&listener).expect("error accepting workers");
     write_image(&args[1], &pixels, bounds).expect("error writing PNG file");
+    println!(
+        "Rendered by {} workers, {} tiles reassigned",
+        summary.workers, summary.reassigned
+    );
+}
+
+/// `work`子命令：连接到协调者，渲染它分来的瓦片，直到图像完成。
+fn worker(args: Vec<String>, threads: usize) {
+    if args.len() != 2 {
+        eprintln!("Usage: {} work HOST:PORT", args[0]);
+        eprintln!("Example: {} work 127.0.0.1:7878", args[0]);
+        std::process::exit(1);
+    }
+    let stream = TcpStream::connect(&args[1]).unwrap_or_else(|err| {
+        eprintln!("error connecting to {}: {}", args[1], err);
+        std::process::exit(1);
+    });
+    match work(stream, threads) {
+        Ok(tiles) => println!("Rendered {} tiles", tiles),
+        Err(err) => {
+            eprintln!("error talking to coordinator: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `heightmap`子命令：把逃逸时间（或者平滑值）写成 16 位高度图，
+/// 可选地再导出 OBJ 或 STL 网格用于 3D 打印。
+fn heightmap(mut args: Vec<String>, threads: usize) {
+    let field = take_option(&mut args, "--field").unwrap_or_else(|| "smooth".to_string());
+    let limit = take_option(&mut args, "--limit").unwrap_or_else(|| "1000".to_string());
+    let scale = take_option(&mut args, "--scale");
+    let step = take_option(&mut args, "--step").unwrap_or_else(|| "1".to_string());
+    let mesh_path = take_option(&mut args, "--mesh");
+    if args.len() != 5 {
+        eprintln!(
+            "Usage: {} heightmap [--field=escape|smooth] [--limit=N] [--mesh=FILE.obj|FILE.stl] [--scale=HEIGHT] [--step=N] FILE PIXELS UPPERLEFT LOWERRIGHT",
+            args[0]
+        );
+        eprintln!(
+            "Example: {} heightmap --mesh=mandel.stl --step=4 height.png 1000x750 -2,1.2 1,-1.2",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+    let field: Field = field.parse().unwrap_or_else(|err| {
+        eprintln!("error parsing field: {}", err);
+        std::process::exit(1);
+    });
+    let limit = limit.parse().expect("error parsing iteration limit");
+    let bounds = bounds_arg(&args[2]);
+    let upper_left = complex_arg(&args[3], "upper left corner point");
+    let lower_right = complex_arg(&args[4], "lower right corner point");
+
+    let heights = HeightField::compute(bounds, upper_left, lower_right, limit, field, threads);
+    heights
+        .write_png16(&args[1])
+        .expect("error writing PNG file");
+
+    if let Some(path) = mesh_path {
+        // 默认最高处的高度是视口宽度的十分之一
+        let scale = match scale {
+            Some(scale) => scale.parse().expect("error parsing vertical scale"),
+            None => (lower_right.re - upper_left.re).abs() / 10.0,
+        };
+        let step = step.parse().expect("error parsing decimation step");
+        let mesh = heights.mesh(scale, step);
+        let output = fs::File::create(&path).map(std::io::BufWriter::new);
+        let written = if path.ends_with(".stl") {
+            output.and_then(|file| mesh.write_stl(file))
+        } else {
+            output.and_then(|file| mesh.write_obj(file))
+        };
+        written.expect("error writing mesh file");
+        println!(
+            "{}: {} vertices, {} triangles",
+            path,
+            mesh.vertices.len(),
+            mesh.triangles.len()
+        );
+    }
+}
+
+/// `nucleus`子命令：从粗略的位置出发找到小曼德勃罗集的核心，打印它的周期、
+/// 大小和一个正好装下它的视口。
+fn nucleus(mut args: Vec<String>) {
+    let period = take_option(&mut args, "--period");
+    let limit = take_option(&mut args, "--limit").unwrap_or_else(|| "10000".to_string());
+    let pixels = take_option(&mut args, "--pixels").unwrap_or_else(|| "1000x750".to_string());
+    if args.len() != 2 {
+        eprintln!(
+            "Usage: {} nucleus [--period=N] [--limit=N] [--pixels=WxH] POINT",
+            args[0]
+        );
+        eprintln!("Example: {} nucleus --period=3 -1.75,0.01", args[0]);
+        std::process::exit(1);
+    }
+    let guess = complex_arg(&args[1], "point");
+    let bounds = bounds_arg(&pixels);
+    // 没有给出周期时用原子域的方法估计
+    let period = match period {
+        Some(period) => period.parse().expect("error parsing period"),
+        None => estimate_period(guess, limit.parse().expect("error parsing iteration limit")),
+    };
+
+    let Some(nucleus) = find_nucleus(guess, period) else {
+        eprintln!(
+            "Newton's method did not converge to a nucleus of period {} near {}",
+            period, args[1]
+        );
+        std::process::exit(1);
+    };
+    let viewport = nucleus.viewport(bounds);
+    println!("nucleus: {},{}", nucleus.center.re, nucleus.center.im);
+    println!("period:  {}", nucleus.period);
+    println!(
+        "size:    {:e} (angle {:.4} rad)",
+        nucleus.size.norm(),
+        nucleus.size.arg()
+    );
+    println!(
+        "{} minibrot.png {}x{} {}",
+        args[0], bounds.0, bounds.1, viewport
+    );
+}
+
+/// `probe`子命令：打印单个点的迭代情况，可以把轨迹写成 CSV/JSON 或者画到图上。
+fn probe_point(mut args: Vec<String>) {
+    let limit = take_option(&mut args, "--limit").unwrap_or_else(|| "1000".to_string());
+    let orbit_path = take_option(&mut args, "--orbit");
+    let plot_path = take_option(&mut args, "--plot");
+    let pixels = take_option(&mut args, "--pixels").unwrap_or_else(|| "800x600".to_string());
+    if args.len() != 2 {
+        eprintln!(
+            "Usage: {} probe [--limit=N] [--orbit=FILE.csv|FILE.json] [--plot=FILE.png] [--pixels=WxH] POINT",
+            args[0]
+        );
+        eprintln!("Example: {} probe --plot=orbit.png -0.1+0.65i", args[0]);
+        std::process::exit(1);
+    }
+    let c = complex_arg(&args[1], "point");
+    let result = probe(c, limit.parse().expect("error parsing iteration limit"));
+
+    println!("c:             {},{}", c.re, c.im);
+    match result.escape_time {
+        Some(n) => println!("escape time:   {}", n),
+        None => println!("escape time:   none (limit {})", result.limit),
+    }
+    if let Some(smooth) = result.smooth {
+        println!("smooth value:  {:.6}", smooth);
+    }
+    match result.classification {
+        Classification::Interior { period } => {
+            println!(
+                "class:         interior (attracting cycle of period {})",
+                period
+            )
+        }
+        other => println!("class:         {}", other.name()),
+    }
+    println!("period:        {}", result.period);
+    // 轨迹可能很长，终端里只显示开头
+    const SHOWN: usize = 20;
+    println!("orbit:");
+    for (i, z) in result.orbit.iter().take(SHOWN).enumerate() {
+        println!("  {:>5}  {},{}", i + 1, z.re, z.im);
+    }
+    if result.orbit.len() > SHOWN {
+        println!("  ... {} more", result.orbit.len() - SHOWN);
+    }
+
+    if let Some(path) = orbit_path {
+        let written = if path.ends_with(".json") {
+            fs::write(&path, result.to_json())
+        } else {
+            fs::File::create(&path).and_then(|file| result.write_csv(std::io::BufWriter::new(file)))
+        };
+        written.expect("error writing orbit file");
+    }
+    if let Some(path) = plot_path {
+        let bounds = bounds_arg(&pixels);
+        let viewport = result.orbit_viewport(bounds);
+        let mut pixels = vec![0; bounds.0 * bounds.1 * 3];
+        plot_orbit(&mut pixels, bounds, viewport, &result);
+        write_image_rgb(&path, &pixels, bounds).expect("error writing PNG file");
+        println!("plotted on:    {}", viewport);
+    }
+}
+
+/// 解析命令行参数中的复数。出错时打印出错的字符位置并退出。
+fn complex_arg(arg: &str, what: &str) -> Complex<f64> {
+    parse_complex(arg).unwrap_or_else(|err| {
+        eprintln!("error parsing {}: {}", what, err);
+        eprintln!("{}", err.pointer(arg));
+        std::process::exit(1);
+    })
+}
+
+/// 从`args`中取出开关`flag`（例如`"--distance"`），返回它是否出现过。
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let before = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != before
+}
+
+/// 从`args`中取出形如`--name=value`的选项，返回它的值。
+/// 如果选项出现了多次，以最后一次为准。
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    let mut value = None;
+    args.retain(|arg| match arg.strip_prefix(&prefix) {
+        Some(v) => {
+            value = Some(v.to_string());
+            false
+        }
+        None => true,
+    });
+    value
 }
 
 /*fn main() {
@@ -71,12 +1104,45 @@ fn main() {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use ch02::concurrency::parse::pixel_to_point;
+
+    #[test]
+    fn test_take_flag() {
+        let mut args: Vec<String> = ["mandel", "--distance", "a.png"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(take_flag(&mut args, "--distance"));
+        assert_eq!(args, vec!["mandel", "a.png"]);
+        assert!(!take_flag(&mut args, "--distance"));
+    }
+
+    #[test]
+    fn test_take_option() {
+        let mut args: Vec<String> = ["mandel", "--seed=3", "a.png", "--seed=4"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(take_option(&mut args, "--seed"), Some("4".to_string()));
+        assert_eq!(args, vec!["mandel", "a.png"]);
+        assert_eq!(take_option(&mut args, "--seed"), None);
+    }
+
     #[test]
     fn test_pixel_to_point() {
-        let bounds = (4, 5);
-        let pixels = vec![0; bounds.0 * bounds.1];
-        println!("{:?}", pixels);
-        let chunks: Vec<_> = pixels.chunks(3).collect();
-        println!("{:?}", chunks);
+        let upper_left = Complex { re: -1.0, im: 1.0 };
+        let lower_right = Complex { re: 1.0, im: -1.0 };
+        let point = |pixel| pixel_to_point((100, 200), pixel, upper_left, lower_right);
+        assert_eq!(
+            point((25, 175)),
+            Complex {
+                re: -0.5,
+                im: -0.75
+            }
+        );
+        assert_eq!(point((0, 0)), upper_left);
+        assert_eq!(point((100, 200)), lower_right);
+        assert_eq!(point((50, 100)), Complex { re: 0.0, im: 0.0 });
     }
 }
@@ -0,0 +1,78 @@
+//! 分布式渲染的端到端测试：启动一个`coordinate`进程和几个`work`进程，
+//! 另有一个连接领到瓦片后就断开，检查这块瓦片被重新分配，拼出来的图像
+//! 和在本地直接渲染的结果相同。
+
+use ch02::concurrency::draw::render;
+use num::Complex;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+
+const BINARY: &str = env!("CARGO_BIN_EXE_ch02-a-tour-of-rust");
+
+#[test]
+fn coordinator_and_worker_processes() {
+    let directory = env::temp_dir().join(format!("ch02-distributed-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let output = directory.join("distributed.png");
+
+    let bounds = (120, 90);
+    let upper_left = Complex { re: -2.0, im: 1.2 };
+    let lower_right = Complex { re: 1.0, im: -1.2 };
+    let mut coordinator = Command::new(BINARY)
+        .args([
+            "coordinate",
+            "--listen=127.0.0.1:0",
+            "--tile=16",
+            "--timeout=5",
+        ])
+        .arg(&output)
+        .args(["120x90", "-2,1.2", "1,-1.2"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(coordinator.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    let address = line
+        .trim()
+        .strip_prefix("Listening on ")
+        .unwrap_or_else(|| panic!("unexpected output {:?}", line))
+        .to_string();
+
+    // 一个出故障的工作进程：确实领到了一块瓦片，还没交回就断开了
+    let failed = TcpStream::connect(&address).unwrap();
+    let mut request = String::new();
+    BufReader::new(&failed).read_line(&mut request).unwrap();
+    assert!(request.starts_with("render "), "{:?}", request);
+    drop(failed);
+
+    // 工作进程使用不同的线程数，就像不同配置的机器
+    let mut workers: Vec<_> = [1, 3, 7]
+        .into_iter()
+        .map(|threads| {
+            Command::new(BINARY)
+                .args(["work", &format!("--threads={}", threads), &address])
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+
+    assert!(coordinator.wait().unwrap().success());
+    for worker in &mut workers {
+        assert!(worker.wait().unwrap().success());
+    }
+    line.clear();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line.trim(), "Rendered by 4 workers, 1 tiles reassigned");
+
+    let image = image::open(&output).unwrap().to_luma();
+    assert_eq!(image.dimensions(), (120, 90));
+    let mut expected = vec![0; bounds.0 * bounds.1];
+    render(&mut expected, bounds, upper_left, lower_right);
+    assert_eq!(image.into_raw(), expected);
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,178 @@
+//! 黄金图像回归测试：用固定的场景渲染小图，和`tests/fixtures`里的参考图比较。
+//!
+//! 渲染结果有意改变时，运行`UPDATE_GOLDEN=1 cargo test --test golden`
+//! 重新生成参考图，然后检查并提交新的 PNG 文件。比较失败时，实际结果和
+//! 差异图会写到`target/tmp/golden`下面，差异图中越亮的像素差得越多。
+
+use ch02::concurrency::draw::{Band, render_band, render_bands, render_distance, write_image};
+use num::Complex;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// 每个像素允许的灰度差。
+const TOLERANCE: u8 = 2;
+
+/// 允许超出`TOLERANCE`的像素比例。不同平台上的浮点运算可能让边界上
+/// 个别像素的迭代次数不同。
+const MAX_OUTLIER_FRACTION: f64 = 0.005;
+
+type Renderer = fn(&mut [u8], Band);
+
+struct Scene {
+    name: &'static str,
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    renderer: Renderer,
+}
+
+fn render_in_eight_bands(pixels: &mut [u8], band: Band) {
+    render_bands(
+        pixels,
+        band.bounds,
+        band.upper_left,
+        band.lower_right,
+        8,
+        render_band,
+    );
+}
+
+fn scenes() -> Vec<Scene> {
+    let full = (Complex { re: -2.5, im: 1.2 }, Complex { re: 1.0, im: -1.2 });
+    let seahorse = (
+        Complex {
+            re: -1.20,
+            im: 0.35,
+        },
+        Complex { re: -1.0, im: 0.20 },
+    );
+    vec![
+        Scene {
+            name: "full",
+            bounds: (96, 66),
+            upper_left: full.0,
+            lower_right: full.1,
+            renderer: render_band,
+        },
+        Scene {
+            name: "seahorse",
+            bounds: (80, 60),
+            upper_left: seahorse.0,
+            lower_right: seahorse.1,
+            renderer: render_band,
+        },
+        Scene {
+            name: "seahorse_bands",
+            bounds: (80, 60),
+            upper_left: seahorse.0,
+            lower_right: seahorse.1,
+            renderer: render_in_eight_bands,
+        },
+        Scene {
+            name: "full_distance",
+            bounds: (96, 66),
+            upper_left: full.0,
+            lower_right: full.1,
+            renderer: render_distance,
+        },
+    ]
+}
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(format!("{}.png", name))
+}
+
+/// 读取灰度参考图，返回像素和宽高。
+fn load_fixture(name: &str) -> (Vec<u8>, (usize, usize)) {
+    let path = fixture_path(name);
+    let image = image::open(&path)
+        .unwrap_or_else(|err| {
+            panic!(
+                "error reading {}: {} (run with UPDATE_GOLDEN=1 to create it)",
+                path.display(),
+                err
+            )
+        })
+        .to_luma();
+    let bounds = (image.width() as usize, image.height() as usize);
+    (image.into_raw(), bounds)
+}
+
+/// 比较`actual`和`expected`，返回超出容差的像素数以及差异图。
+fn compare(actual: &[u8], expected: &[u8]) -> (usize, Vec<u8>) {
+    let diff: Vec<u8> = actual
+        .iter()
+        .zip(expected)
+        .map(|(&a, &e)| a.abs_diff(e))
+        .collect();
+    let outliers = diff.iter().filter(|&&d| d > TOLERANCE).count();
+    // 把差异放大，让一级的差别也看得见
+    let image = diff.iter().map(|&d| d.saturating_mul(16)).collect();
+    (outliers, image)
+}
+
+fn check_scene(scene: &Scene) -> Result<(), String> {
+    let mut actual = vec![0; scene.bounds.0 * scene.bounds.1];
+    (scene.renderer)(
+        &mut actual,
+        Band::whole(scene.bounds, scene.upper_left, scene.lower_right),
+    );
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        let path = fixture_path(scene.name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        write_image(path.to_str().unwrap(), &actual, scene.bounds).unwrap();
+        return Ok(());
+    }
+
+    let (expected, bounds) = load_fixture(scene.name);
+    if bounds != scene.bounds {
+        return Err(format!(
+            "{}: fixture is {}x{}, scene is {}x{}",
+            scene.name, bounds.0, bounds.1, scene.bounds.0, scene.bounds.1
+        ));
+    }
+    let (outliers, diff) = compare(&actual, &expected);
+    let allowed = (actual.len() as f64 * MAX_OUTLIER_FRACTION) as usize;
+    if outliers <= allowed {
+        return Ok(());
+    }
+
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    fs::create_dir_all(&out_dir).unwrap();
+    let actual_path = out_dir.join(format!("{}.actual.png", scene.name));
+    let diff_path = out_dir.join(format!("{}.diff.png", scene.name));
+    write_image(actual_path.to_str().unwrap(), &actual, scene.bounds).unwrap();
+    write_image(diff_path.to_str().unwrap(), &diff, scene.bounds).unwrap();
+    Err(format!(
+        "{}: {} pixels differ by more than {} (allowed {}); see {} and {}",
+        scene.name,
+        outliers,
+        TOLERANCE,
+        allowed,
+        actual_path.display(),
+        diff_path.display()
+    ))
+}
+
+#[test]
+fn golden_images() {
+    // 先检查所有场景再报告，一次就能看到所有失败的场景
+    let failures: Vec<String> = scenes()
+        .iter()
+        .filter_map(|scene| check_scene(scene).err())
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn compare_counts_outliers() {
+    let expected = [0, 10, 100, 200];
+    let actual = [1, 10, 90, 255];
+    let (outliers, diff) = compare(&actual, &expected);
+    assert_eq!(outliers, 2);
+    assert_eq!(diff, vec![16, 0, 160, 255]);
+}